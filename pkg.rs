@@ -114,12 +114,21 @@ extern \\{
     static pcre_free: extern \"C\" fn(ptr: *c_void);
 
     fn pcre_compile(pattern: *c_char, options: options, errptr: *mut *c_char, erroffset: *mut c_int, tableptr: *c_uchar) -> *pcre;
+    fn pcre_config(what: c_int, where: *mut c_void) -> c_int;
     fn pcre_exec(code: *pcre, extra: *pcre_extra, subject: *c_char, length: c_int, startoffset: c_int, options: options, ovector: *mut c_int, ovecsize: c_int) -> c_int;
     fn pcre_version() -> *c_char;
 \\}
 
 fn main () \\{
     unsafe \\{
+        // Print the capabilities that rust-pcre depends on, one per line, before the version.
+        let mut utf8: c_int = 0;
+        pcre_config(0, &mut utf8 as *mut c_int as *mut c_void);
+        println!(\"utf8=\\{\\}\", utf8);
+        let mut jit: c_int = 0;
+        pcre_config(9, &mut jit as *mut c_int as *mut c_void);
+        println!(\"jit=\\{\\}\", jit);
+
         let version_cstring = CString::new(pcre_version(), false);
         let version_str = version_cstring.as_str().unwrap().to_owned();
 
@@ -197,7 +206,7 @@ fn main () \\{
 
     debug!("libpcre version {:s}", version_str);
 
-    let min_required_version = Version::parse("8.20").unwrap();
+    let min_required_version = Version::parse("8.32").unwrap();
     let pcre_version = match Version::parse(version_str) {
         None               => fail!("Package script error: Failed to parse version string '{}'", version_str),
         Some(pcre_version) => pcre_version
@@ -207,6 +216,13 @@ fn main () \\{
         fail!("Package script error: Found libpcre version {}, but at least version {} is required", version_str, min_required_version.to_str());
     }
 
+    // rust-pcre compiles patterns in UTF-8 mode, while JIT support is optional.
+    let has_capability = |name: &str| output_str.lines().any(|line| line == format!("{}=1", name).as_slice());
+    if !has_capability("utf8") {
+        fail!("Package script error: libpcre {} was built without UTF-8 support", version_str);
+    }
+    println!("Found libpcre {} (JIT support: {})", version_str, if has_capability("jit") { "yes" } else { "no" });
+
     // Create directories `bin` and `lib`
     let bin_path = workspace_path.join("bin");
     if !bin_path.exists() {
@@ -0,0 +1,75 @@
+extern crate pcre;
+extern crate collections;
+extern crate test;
+
+use collections::EnumSet;
+use pcre::{ExecOption, Pcre};
+use std::str;
+use test::BenchHarness;
+
+/// Returns a subject of `len` bytes of log lines which only matches `needle\d+` at the end.
+fn large_subject(len: uint) -> ~str {
+    let line = "2014-04-01 12:00:00 INFO nothing to see here\n";
+    let mut subject = str::with_capacity(len + 16);
+    while subject.len() + line.len() < len {
+        subject.push_str(line);
+    }
+    subject.push_str("needle42");
+    subject
+}
+
+static SUBJECT_LEN: uint = 4 * 1024 * 1024;
+
+#[bench]
+fn bench_exec_4mb(b: &mut BenchHarness) {
+    let re = Pcre::compile("needle(\\d+)").unwrap();
+    let subject = large_subject(SUBJECT_LEN);
+    b.bytes = subject.len() as u64;
+    b.iter(|| {
+        assert!(re.exec(subject).unwrap().is_some());
+    });
+}
+
+#[bench]
+fn bench_exec_from_end_4mb(b: &mut BenchHarness) {
+    // Only the last few bytes are searched, so the time is dominated by any per-call
+    // handling of the whole subject.
+    let re = Pcre::compile("needle(\\d+)").unwrap();
+    let subject = large_subject(SUBJECT_LEN);
+    let no_options: EnumSet<ExecOption> = EnumSet::empty();
+    let start = subject.len() - 8;
+    b.iter(|| {
+        assert!(re.exec_from_with_options(subject, start, &no_options).unwrap().is_some());
+    });
+}
+
+#[bench]
+fn bench_matches_4mb(b: &mut BenchHarness) {
+    let re = Pcre::compile("INFO").unwrap();
+    let subject = large_subject(SUBJECT_LEN);
+    b.bytes = subject.len() as u64;
+    b.iter(|| {
+        assert!(re.matches(subject).count() > 0u);
+    });
+}
+
+#[bench]
+fn bench_exec_bytes_from_end_4mb(b: &mut BenchHarness) {
+    let re = Pcre::compile_bytes("needle(\\d+)".as_bytes()).unwrap();
+    let subject = large_subject(SUBJECT_LEN);
+    let no_options: EnumSet<ExecOption> = EnumSet::empty();
+    let start = subject.len() - 8;
+    b.iter(|| {
+        assert!(re.exec_bytes_from_with_options(subject.as_bytes(), start, &no_options).unwrap().is_some());
+    });
+}
+
+#[bench]
+fn bench_to_c_str_4mb(b: &mut BenchHarness) {
+    // The cost of the copy of the subject which every match attempt used to make.
+    let subject = large_subject(SUBJECT_LEN);
+    b.bytes = subject.len() as u64;
+    b.iter(|| {
+        unsafe { subject.to_c_str_unchecked() }
+    });
+}
@@ -9,17 +9,20 @@
 #[allow(non_camel_case_types)];
 use collections::enum_set::{EnumSet};
 use std::c_str::{CString};
-use std::libc::{c_int, c_char, c_void, c_uchar, c_ulong, };
+use std::libc::{c_int, c_char, c_void, c_uchar, c_ulong, size_t};
 use std::ptr;
 use std::ptr::{RawPtr};
 use std::result::{Result};
+use sync::mutex::{StaticMutex, MUTEX_INIT};
 
 mod native;
 
 pub type compile_options = c_int;
+pub type config_field = c_int;
 pub type exec_options = c_int;
 pub type fullinfo_field = c_int;
 pub struct pcre;
+pub struct pcre_jit_stack;
 pub type pcre_error = c_int;
 
 pub struct pcre_extra {
@@ -33,27 +36,127 @@ pub struct pcre_extra {
     executable_jit: *mut c_void,
 }
 
+impl pcre_extra {
+    /// Returns an extra block with no fields set.
+    pub fn empty() -> pcre_extra {
+        pcre_extra {
+            flags: 0,
+            study_data: ptr::mut_null(),
+            match_limit: 0,
+            callout_data: ptr::mut_null(),
+            tables: ptr::null(),
+            match_limit_recursion: 0,
+            mark: ptr::mut_null(),
+            executable_jit: ptr::mut_null()
+        }
+    }
+}
+
+pub struct pcre_callout_block {
+    version: c_int,
+    callout_number: c_int,
+    offset_vector: *c_int,
+    subject: *c_char,
+    subject_length: c_int,
+    start_match: c_int,
+    current_position: c_int,
+    capture_top: c_int,
+    capture_last: c_int,
+    callout_data: *mut c_void,
+    pattern_position: c_int,
+    next_item_length: c_int,
+    mark: *c_uchar,
+}
+
 pub type study_options = c_int;
 
 pub type extra_options = c_int;
 
+pub static PCRE_EXTRA_STUDY_DATA: c_ulong = 0x0001;
+pub static PCRE_EXTRA_MATCH_LIMIT: c_ulong = 0x0002;
+pub static PCRE_EXTRA_CALLOUT_DATA: c_ulong = 0x0004;
+pub static PCRE_EXTRA_TABLES: c_ulong = 0x0008;
+pub static PCRE_EXTRA_MATCH_LIMIT_RECURSION: c_ulong = 0x0010;
+pub static PCRE_EXTRA_MARK: c_ulong = 0x0020;
+
+/// The size of the character tables built by `pcre_maketables()`.
+pub static PCRE_TABLES_LENGTH: uint = 1088;
+
 pub static PCRE_UTF8: c_int = 0x00000800;
 pub static PCRE_NO_UTF8_CHECK: c_int = 0x00002000;
 
+pub static PCRE_DFA_SHORTEST: exec_options = 0x00010000;
+pub static PCRE_DFA_RESTART: exec_options = 0x00020000;
+
 pub static PCRE_ERROR_NOMATCH: pcre_error = -1;
 pub static PCRE_ERROR_NULL: pcre_error = -2;
+pub static PCRE_ERROR_BADOPTION: pcre_error = -3;
+pub static PCRE_ERROR_BADMAGIC: pcre_error = -4;
+pub static PCRE_ERROR_NOMEMORY: pcre_error = -6;
+pub static PCRE_ERROR_MATCHLIMIT: pcre_error = -8;
+pub static PCRE_ERROR_CALLOUT: pcre_error = -9;
+pub static PCRE_ERROR_BADUTF8: pcre_error = -10;
+pub static PCRE_ERROR_BADUTF8_OFFSET: pcre_error = -11;
+pub static PCRE_ERROR_PARTIAL: pcre_error = -12;
+pub static PCRE_ERROR_INTERNAL: pcre_error = -14;
+pub static PCRE_ERROR_DFA_WSSIZE: pcre_error = -19;
+pub static PCRE_ERROR_RECURSIONLIMIT: pcre_error = -21;
+pub static PCRE_ERROR_BADOFFSET: pcre_error = -24;
+pub static PCRE_ERROR_SHORTUTF8: pcre_error = -25;
+pub static PCRE_ERROR_JIT_STACKLIMIT: pcre_error = -27;
+pub static PCRE_ERROR_BADENDIANNESS: pcre_error = -29;
+pub static PCRE_ERROR_DFA_BADRESTART: pcre_error = -30;
+pub static PCRE_ERROR_JIT_BADOPTION: pcre_error = -31;
+pub static PCRE_ERROR_UNSET: pcre_error = -33;
+
+pub static PCRE_CONFIG_UTF8: config_field = 0;
+pub static PCRE_CONFIG_NEWLINE: config_field = 1;
+pub static PCRE_CONFIG_LINK_SIZE: config_field = 2;
+pub static PCRE_CONFIG_POSIX_MALLOC_THRESHOLD: config_field = 3;
+pub static PCRE_CONFIG_MATCH_LIMIT: config_field = 4;
+pub static PCRE_CONFIG_STACKRECURSE: config_field = 5;
+pub static PCRE_CONFIG_UNICODE_PROPERTIES: config_field = 6;
+pub static PCRE_CONFIG_MATCH_LIMIT_RECURSION: config_field = 7;
+pub static PCRE_CONFIG_BSR: config_field = 8;
+pub static PCRE_CONFIG_JIT: config_field = 9;
+pub static PCRE_CONFIG_UTF16: config_field = 10;
+pub static PCRE_CONFIG_JITTARGET: config_field = 11;
+pub static PCRE_CONFIG_UTF32: config_field = 12;
+pub static PCRE_CONFIG_PARENS_LIMIT: config_field = 13;
 
+pub static PCRE_INFO_OPTIONS: fullinfo_field = 0;
+pub static PCRE_INFO_SIZE: fullinfo_field = 1;
 pub static PCRE_INFO_CAPTURECOUNT: fullinfo_field = 2;
+pub static PCRE_INFO_BACKREFMAX: fullinfo_field = 3;
+pub static PCRE_INFO_FIRSTTABLE: fullinfo_field = 5;
 pub static PCRE_INFO_NAMEENTRYSIZE: fullinfo_field = 7;
 pub static PCRE_INFO_NAMECOUNT: fullinfo_field = 8;
 pub static PCRE_INFO_NAMETABLE: fullinfo_field = 9;
+pub static PCRE_INFO_STUDYSIZE: fullinfo_field = 10;
+pub static PCRE_INFO_OKPARTIAL: fullinfo_field = 12;
+pub static PCRE_INFO_HASCRORLF: fullinfo_field = 14;
+pub static PCRE_INFO_MINLENGTH: fullinfo_field = 15;
+pub static PCRE_INFO_JIT: fullinfo_field = 16;
+pub static PCRE_INFO_JITSIZE: fullinfo_field = 17;
+pub static PCRE_INFO_MAXLOOKBEHIND: fullinfo_field = 18;
+pub static PCRE_INFO_FIRSTCHARACTER: fullinfo_field = 19;
+pub static PCRE_INFO_FIRSTCHARACTERFLAGS: fullinfo_field = 20;
+pub static PCRE_INFO_REQUIREDCHAR: fullinfo_field = 21;
+pub static PCRE_INFO_REQUIREDCHARFLAGS: fullinfo_field = 22;
+pub static PCRE_INFO_MATCHLIMIT: fullinfo_field = 23;
+pub static PCRE_INFO_RECURSIONLIMIT: fullinfo_field = 24;
+pub static PCRE_INFO_MATCH_EMPTY: fullinfo_field = 25;
 
-pub unsafe fn pcre_compile(pattern: *c_char, options: &EnumSet<::CompileOption>, tableptr: *c_uchar) -> Result<*mut pcre, (Option<~str>, c_int)> {
+pub unsafe fn pcre_compile2(pattern: *c_char, options: &EnumSet<::CompileOption>, utf8: bool, tableptr: *c_uchar) -> Result<*mut pcre, (c_int, Option<~str>, c_int)> {
     assert!(pattern.is_not_null());
-    let converted_options = options.iter().fold(0, |converted_options, option| converted_options | (option as compile_options)) | PCRE_UTF8 | PCRE_NO_UTF8_CHECK;
+    let mut converted_options = options.iter().fold(0, |converted_options, option| converted_options | (option as compile_options));
+    if utf8 {
+        converted_options |= PCRE_UTF8 | PCRE_NO_UTF8_CHECK;
+    }
+    let mut errorcode: c_int = 0;
     let mut err: *c_char = ptr::null();
     let mut erroffset: c_int = 0;
-    let code = native::pcre_compile(pattern, converted_options, &mut err, &mut erroffset, tableptr);
+    let code = native::pcre_compile2(pattern, converted_options, &mut errorcode, &mut err, &mut erroffset, tableptr);
 
     if code.is_null() {
         // "Otherwise, if  compilation  of  a  pattern fails, pcre_compile() returns
@@ -64,8 +167,8 @@ pub unsafe fn pcre_compile(pattern: *c_char, options: &EnumSet<::CompileOption>,
         let err_cstring = CString::new(err, false);
 
         match err_cstring.as_str() {
-            None => Err((None, erroffset)),
-            Some(err_str) => Err((Some(err_str.to_owned()), erroffset))
+            None => Err((errorcode, None, erroffset)),
+            Some(err_str) => Err((errorcode, Some(err_str.to_owned()), erroffset))
         }
     } else {
         assert!(code.is_not_null());
@@ -75,18 +178,43 @@ pub unsafe fn pcre_compile(pattern: *c_char, options: &EnumSet<::CompileOption>,
     }
 }
 
-pub unsafe fn pcre_exec(code: *pcre, extra: *pcre_extra, subject: *c_char, length: c_int, startoffset: c_int, options: &EnumSet<::ExecOption>, ovector: *mut c_int, ovecsize: c_int) -> c_int {
+pub unsafe fn pcre_assign_jit_stack(extra: *mut pcre_extra, callback: Option<extern "C" fn(data: *mut c_void) -> *mut pcre_jit_stack>, data: *mut c_void) {
+    assert!(extra.is_not_null());
+    native::pcre_assign_jit_stack(extra, callback, data);
+}
+
+pub unsafe fn pcre_config(what: config_field, where: *mut c_void) -> Result<(), pcre_error> {
+    assert!(where.is_not_null());
+    let rc = native::pcre_config(what, where);
+    if rc < 0 {
+        Err(rc)
+    } else {
+        Ok(())
+    }
+}
+
+pub unsafe fn pcre_dfa_exec(code: *pcre, extra: *pcre_extra, subject: *c_char, length: c_int, startoffset: c_int, options: &EnumSet<::ExecOption>, dfa_options: exec_options, check_utf8: bool, ovector: *mut c_int, ovecsize: c_int, workspace: *mut c_int, wscount: c_int) -> c_int {
     assert!(code.is_not_null());
-    assert!(ovecsize >= 0 && ovecsize % 3 == 0);
-    let converted_options = options.iter().fold(0, |converted_options, option| converted_options | (option as compile_options)) | PCRE_NO_UTF8_CHECK;
-    let rc = native::pcre_exec(code, extra, subject, length, startoffset, converted_options, ovector, ovecsize);
-    if rc == PCRE_ERROR_NOMATCH {
-        return -1;
-    } else if rc < 0 && rc != PCRE_ERROR_NULL {
-        fail!("pcre_exec");
+    // "The smallest possible vector is 2 elements, since each match is described by a
+    // pair of offsets."
+    assert!(ovecsize >= 2 && ovecsize % 2 == 0);
+    let mut converted_options = options.iter().fold(dfa_options, |converted_options, option| converted_options | (option as exec_options));
+    if !check_utf8 {
+        converted_options |= PCRE_NO_UTF8_CHECK;
     }
+    native::pcre_dfa_exec(code, extra, subject, length, startoffset, converted_options, ovector, ovecsize, workspace, wscount)
+}
 
-    rc
+pub unsafe fn pcre_exec(code: *pcre, extra: *pcre_extra, subject: *c_char, length: c_int, startoffset: c_int, options: &EnumSet<::ExecOption>, check_utf8: bool, ovector: *mut c_int, ovecsize: c_int) -> c_int {
+    assert!(code.is_not_null());
+    assert!(ovecsize >= 0 && ovecsize % 3 == 0);
+    let mut converted_options = options.iter().fold(0, |converted_options, option| converted_options | (option as compile_options));
+    if !check_utf8 {
+        converted_options |= PCRE_NO_UTF8_CHECK;
+    }
+    // Negative return codes other than PCRE_ERROR_NOMATCH are converted into an
+    // `ExecError` by the caller.
+    native::pcre_exec(code, extra, subject, length, startoffset, converted_options, ovector, ovecsize)
 }
 
 pub unsafe fn pcre_free(ptr: *mut c_void) {
@@ -97,26 +225,85 @@ pub unsafe fn pcre_free_study(extra: *mut pcre_extra) {
     native::pcre_free_study(extra);
 }
 
-pub unsafe fn pcre_fullinfo(code: *pcre, extra: *pcre_extra, what: fullinfo_field, where: *mut c_void) {
+pub unsafe fn pcre_fullinfo(code: *pcre, extra: *pcre_extra, what: fullinfo_field, where: *mut c_void) -> Result<(), pcre_error> {
     assert!(code.is_not_null());
+    assert!(where.is_not_null());
     let rc = native::pcre_fullinfo(code, extra, what, where);
-    if rc < 0 && rc != PCRE_ERROR_NULL {
-        fail!("pcre_fullinfo");
+    if rc < 0 {
+        Err(rc)
+    } else {
+        Ok(())
+    }
+}
+
+pub unsafe fn pcre_jit_exec(code: *pcre, extra: *pcre_extra, subject: *c_char, length: c_int, startoffset: c_int, options: &EnumSet<::ExecOption>, ovector: *mut c_int, ovecsize: c_int, jstack: *mut pcre_jit_stack) -> c_int {
+    assert!(code.is_not_null());
+    assert!(extra.is_not_null());
+    assert!(ovecsize >= 0 && ovecsize % 3 == 0);
+    // pcre_jit_exec() skips the sanity checks of pcre_exec(), including UTF-8 validation,
+    // so the caller must check the subject and start offset.
+    let converted_options = options.iter().fold(0, |converted_options, option| converted_options | (option as exec_options));
+    native::pcre_jit_exec(code, extra, subject, length, startoffset, converted_options, ovector, ovecsize, jstack)
+}
+
+pub unsafe fn pcre_jit_stack_alloc(startsize: c_int, maxsize: c_int) -> *mut pcre_jit_stack {
+    assert!(startsize > 0 && startsize <= maxsize);
+    native::pcre_jit_stack_alloc(startsize, maxsize)
+}
+
+pub unsafe fn pcre_jit_stack_free(stack: *mut pcre_jit_stack) {
+    native::pcre_jit_stack_free(stack);
+}
+
+pub unsafe fn pcre_malloc(size: uint) -> *mut c_void {
+    native::pcre_malloc(size as size_t)
+}
+
+#[cfg(target_os = "linux")]
+static LC_CTYPE: c_int = 0;
+#[cfg(not(target_os = "linux"))]
+static LC_CTYPE: c_int = 2;
+
+/// Serializes the calls to `setlocale()` and `pcre_maketables()` made by this crate.
+static mut LOCALE_LOCK: StaticMutex = MUTEX_INIT;
+
+/// Builds character tables for the current LC_CTYPE locale, or for `locale` if it is given.
+/// The current locale is restored afterwards. Returns null if `locale` is not available.
+pub unsafe fn pcre_maketables(locale: Option<*c_char>) -> *c_uchar {
+    let _guard = LOCALE_LOCK.lock();
+    match locale {
+        None => native::pcre_maketables(),
+        Some(locale) => {
+            // The string returned by setlocale() may be overwritten by the next call, so
+            // it is copied.
+            let old_locale = CString::new(native::setlocale(LC_CTYPE, ptr::null()), false).as_str().unwrap_or("C").to_owned();
+            if native::setlocale(LC_CTYPE, locale).is_null() {
+                return ptr::null();
+            }
+            let tables = native::pcre_maketables();
+            old_locale.with_c_str(|old_locale_c_str| {
+                native::setlocale(LC_CTYPE, old_locale_c_str);
+            });
+            tables
+        }
     }
 }
 
-pub unsafe fn pcre_refcount(code: *mut ::detail::pcre, adjust: c_int) -> c_int {
+pub unsafe fn pcre_pattern_to_host_byte_order(code: *mut pcre, extra: *mut pcre_extra, tables: *c_uchar) -> Result<(), pcre_error> {
     assert!(code.is_not_null());
-    let curr_refcount = native::pcre_refcount(code, 0);
-    if curr_refcount + adjust < 0 {
-        fail!("refcount underflow");
-    } else if curr_refcount + adjust > 65535 {
-        fail!("refcount overflow");
+    let rc = native::pcre_pattern_to_host_byte_order(code, extra, tables);
+    if rc < 0 {
+        Err(rc)
+    } else {
+        Ok(())
     }
-    native::pcre_refcount(code, adjust)
 }
 
-pub unsafe fn pcre_study(code: *::detail::pcre, options: &EnumSet<::StudyOption>) -> *mut ::detail::pcre_extra {
+pub unsafe fn pcre_set_callout(callout: extern "C" fn(block: *mut pcre_callout_block) -> c_int) {
+    native::pcre_callout = Some(callout);
+}
+
+pub unsafe fn pcre_study(code: *::detail::pcre, options: &EnumSet<::StudyOption>) -> Result<*mut ::detail::pcre_extra, ~str> {
     assert!(code.is_not_null());
     let converted_options = options.iter().fold(0, |converted_options, option| converted_options | (option as study_options));
     let mut err: *c_char = ptr::null();
@@ -128,15 +315,13 @@ pub unsafe fn pcre_study(code: *::detail::pcre, options: &EnumSet<::StudyOption>
     // http://pcre.org/pcre.txt
     if err.is_not_null() {
         let err_cstring = CString::new(err, false);
-        match err_cstring.as_str() {
-            None          => error!("pcre_study() failed"),
-            Some(err_str) => error!("pcre_study() failed: {}", err_str)
-        }
-        fail!("pcre_study");
+        return Err(match err_cstring.as_str() {
+            None          => ~"pcre_study() failed",
+            Some(err_str) => err_str.to_owned()
+        });
     }
-    assert!(err.is_null());
 
-    extra
+    Ok(extra)
 }
 
 pub fn pcre_version() -> ~str {
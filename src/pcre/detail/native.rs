@@ -6,18 +6,30 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::libc::{c_char, c_int, c_uchar, c_void};
+use std::libc::{c_char, c_int, c_uchar, c_void, size_t};
 
 #[link(name = "pcre")]
 extern {
     pub static pcre_free: extern "C" unsafe fn(ptr: *mut c_void);
+    pub static pcre_malloc: extern "C" unsafe fn(size: size_t) -> *mut c_void;
+    pub static mut pcre_callout: Option<extern "C" fn(block: *mut ::detail::pcre_callout_block) -> c_int>;
 
-    pub fn pcre_compile(pattern: *c_char, options: ::detail::compile_options, errptr: *mut *c_char, erroffset: *mut c_int, tableptr: *c_uchar) -> *mut ::detail::pcre;
+    pub fn pcre_assign_jit_stack(extra: *mut ::detail::pcre_extra, callback: Option<extern "C" fn(data: *mut c_void) -> *mut ::detail::pcre_jit_stack>, data: *mut c_void);
+    pub fn pcre_compile2(pattern: *c_char, options: ::detail::compile_options, errorcodeptr: *mut c_int, errptr: *mut *c_char, erroffset: *mut c_int, tableptr: *c_uchar) -> *mut ::detail::pcre;
+    pub fn pcre_config(what: ::detail::config_field, where: *mut c_void) -> c_int;
+    pub fn pcre_dfa_exec(code: *::detail::pcre, extra: *::detail::pcre_extra, subject: *c_char, length: c_int, startoffset: c_int, options: ::detail::exec_options, ovector: *mut c_int, ovecsize: c_int, workspace: *mut c_int, wscount: c_int) -> c_int;
     pub fn pcre_exec(code: *::detail::pcre, extra: *::detail::pcre_extra, subject: *c_char, length: c_int, startoffset: c_int, options: ::detail::exec_options, ovector: *mut c_int, ovecsize: c_int) -> c_int;
     pub fn pcre_free_study(extra: *mut ::detail::pcre_extra);
     pub fn pcre_fullinfo(code: *::detail::pcre, extra: *::detail::pcre_extra, what: ::detail::fullinfo_field, where: *mut c_void) -> c_int;
-    // Note: libpcre's pcre_refcount() function is not thread-safe.
-    pub fn pcre_refcount(code: *mut ::detail::pcre, adjust: c_int) -> c_int;
+    pub fn pcre_jit_exec(code: *::detail::pcre, extra: *::detail::pcre_extra, subject: *c_char, length: c_int, startoffset: c_int, options: ::detail::exec_options, ovector: *mut c_int, ovecsize: c_int, jstack: *mut ::detail::pcre_jit_stack) -> c_int;
+    pub fn pcre_jit_stack_alloc(startsize: c_int, maxsize: c_int) -> *mut ::detail::pcre_jit_stack;
+    pub fn pcre_jit_stack_free(stack: *mut ::detail::pcre_jit_stack);
+    pub fn pcre_maketables() -> *c_uchar;
+    pub fn pcre_pattern_to_host_byte_order(code: *mut ::detail::pcre, extra: *mut ::detail::pcre_extra, tables: *c_uchar) -> c_int;
     pub fn pcre_study(code: *::detail::pcre, options: ::detail::study_options, errptr: *mut *c_char) -> *mut ::detail::pcre_extra;
     pub fn pcre_version() -> *c_char;
 }
+
+extern {
+    pub fn setlocale(category: c_int, locale: *c_char) -> *c_char;
+}
@@ -13,18 +13,27 @@
 #[crate_type = "dylib"];
 #[crate_type = "rlib"];
 
+#[feature(struct_variant)];
+
 extern crate collections;
+extern crate sync;
 
 use collections::treemap::{TreeMap};
 use collections::enum_set::{CLike, EnumSet};
+use collections::lru_cache::LruCache;
 use std::c_str;
-use std::c_str::{CString};
-use std::libc::{c_char, c_int, c_uchar, c_void};
+use std::libc::{c_char, c_int, c_uchar, c_ulong, c_void, size_t};
+use std::local_data;
+use std::mem;
 use std::option::{Option};
 use std::ptr;
 use std::result::{Result};
+use std::str;
+use std::str::{MaybeOwned, Owned, Slice};
 use std::vec;
 use std::fmt;
+use sync::{Arc, MutexArc};
+use sync::one::{Once, ONCE_INIT};
 
 mod detail;
 
@@ -94,28 +103,259 @@ pub enum ExtraOption {
 pub static ExecPartial: ExecOption = ExecPartialSoft;
 pub static ExecNoStartOptimize: ExecOption = ExecNoStartOptimise;
 
+/// Error returned when a pattern fails to compile.
+///
+/// See [render()](#method.render) for a message which shows where the error is.
 pub struct CompilationError {
 
+    priv code: c_int,
+
     priv opt_err: Option<~str>,
 
-    priv erroffset: c_int
+    priv erroffset: c_int,
+
+    /// The pattern which failed to compile.
+    priv pattern: ~[u8]
+
+}
+
+/// The kind of a `CompilationError`, given by the libpcre error code.
+///
+/// Each variant corresponds to one of the compilation error codes listed in the libpcre
+/// manpages, `man 3 pcreapi`; `message()` returns the text libpcre gives for it. Codes
+/// which are not known to this crate are reported as `OtherCompileError`, holding the raw
+/// error code.
+#[deriving(Clone, Eq, Show)]
+pub enum CompileErrorKind {
+    BackslashAtEndError,
+    BackslashCAtEndError,
+    UnrecognizedEscapeError,
+    QuantifierOutOfOrderError,
+    QuantifierTooBigError,
+    MissingBracketError,
+    InvalidClassEscapeError,
+    RangeOutOfOrderError,
+    NothingToRepeatError,
+    UnexpectedRepeatError,
+    UnrecognizedGroupError,
+    PosixClassOutsideClassError,
+    MissingParenthesisError,
+    NonexistentSubpatternError,
+    UnknownOptionBitsError,
+    MissingCommentEndError,
+    PatternTooLargeError,
+    CompileMemoryError,
+    UnmatchedParenthesesError,
+    CodeOverflowError,
+    UnrecognizedAfterLessThanError,
+    LookbehindNotFixedLengthError,
+    MalformedConditionError,
+    ConditionBranchesError,
+    AssertionExpectedError,
+    MalformedRecursionError,
+    UnknownPosixClassError,
+    PosixCollatingElementError,
+    NoUtfSupportError,
+    CharacterValueTooLargeError,
+    InvalidConditionError,
+    BackslashCInLookbehindError,
+    UnsupportedEscapeError,
+    CalloutNumberTooBigError,
+    MissingCalloutEndError,
+    RecursiveLoopError,
+    UnrecognizedAfterPError,
+    MissingNameTerminatorError,
+    DuplicateNameError,
+    InvalidUtf8PatternError,
+    NoPropertySupportError,
+    MalformedPropertyError,
+    UnknownPropertyError,
+    NameTooLongError,
+    TooManyNamesError,
+    OctalValueTooLargeError,
+    WorkspaceOverrunError,
+    ReferencedSubpatternNotFoundError,
+    DefineBranchesError,
+    RepeatedDefineError,
+    InconsistentNewlineError,
+    MalformedBackslashGError,
+    ZeroReferenceError,
+    VerbArgumentError,
+    UnknownVerbError,
+    NumberTooBigError,
+    SubpatternNameExpectedError,
+    DigitExpectedError,
+    JavaScriptBracketError,
+    DifferentNamesError,
+    MarkArgumentError,
+    NoUnicodePropertySupportError,
+    BackslashCNonAsciiError,
+    MalformedBackslashKError,
+    UnknownOpcodeError,
+    BackslashNInClassError,
+    TooManyForwardReferencesError,
+    SurrogateCodePointError,
+    InvalidUtf16PatternError,
+    VerbNameTooLongError,
+    BackslashUValueTooLargeError,
+    InvalidUtf32PatternError,
+    UtfDisabledError,
+    NonHexCharacterError,
+    NonOctalCharacterError,
+    MissingOctalBraceError,
+    ParenthesesTooDeepError,
+    InvalidClassRangeError,
+    NameStartsWithDigitError,
+    ParenthesesStackCheckError,
+    MissingDigitsError,
+    OtherCompileError(int)
+}
+
+/// The state of a match attempt, passed to a callout function.
+///
+/// Callouts are requested with `(?C)` or `(?Cn)` items in the pattern, or automatically
+/// before each item when the pattern is compiled with `AutoCallout`. See the libpcre
+/// manpages, `man 3 pcrecallout`, for more information.
+pub struct CalloutBlock<'a> {
+
+    priv block: &'a detail::pcre_callout_block
+
+}
+
+/// The value returned by a callout function to direct libpcre.
+#[deriving(Clone, Eq)]
+pub enum CalloutResult {
+    /// Continue matching normally.
+    CalloutContinue,
+    /// Fail at the current point and backtrack, as if the item after the callout failed.
+    CalloutFail,
+    /// Abandon the match attempt, which then fails with `CalloutAbortedError`.
+    CalloutAbort
+}
+
+/// Limits on the work libpcre may do in a single match attempt.
+///
+/// A limit of `None` leaves the limit unchanged: per-call limits fall back to the limits
+/// of the `Pcre`, which in turn fall back to libpcre's built-in defaults. See the libpcre
+/// manpages, `man 3 pcreapi`, for the meaning of each limit.
+#[deriving(Clone, Eq)]
+pub struct MatchLimits {
+
+    /// The maximum number of times libpcre's internal `match()` function may be called.
+    match_limit: Option<uint>,
+
+    /// The maximum depth of recursion of libpcre's internal `match()` function.
+    match_limit_recursion: Option<uint>
 
 }
 
+/// The default size of the workspace of a `DfaMatcher`, in ints.
+static DEFAULT_DFA_WORKSPACE_SIZE: uint = 1000u;
+
+/// The default maximum number of matches reported by a `DfaMatcher`.
+static DEFAULT_DFA_MAX_MATCHES: uint = 10u;
+
+/// Owner of a compiled regular expression, which is freed when the last `Pcre` or
+/// `MatchIterator` referring to it is dropped.
+struct CompiledCode {
+    ptr: *detail::pcre,
+    /// The character tables that the compiled code refers to, if not the default tables.
+    tables: Option<Arc<CharTables>>,
+    /// The source of the regular expression and its compile options, kept so that a saved
+    /// regular expression can be recompiled by another version of libpcre.
+    pattern: ~[u8],
+    options: EnumSet<CompileOption>
+}
+
+/// Owner of the study data of a compiled regular expression. `ptr` is null if the regular
+/// expression has not been studied, or if studying found nothing useful. `options` are the
+/// options it was studied with, or `None` if it has not been studied.
+struct StudyData {
+    ptr: *mut detail::pcre_extra,
+    options: Option<EnumSet<StudyOption>>
+}
+
 /// Wrapper for libpcre's `pcre` object (representing a compiled regular expression).
+///
+/// The compiled code and study data are immutable and shared by all of the clones of a
+/// `Pcre`, so cloning is cheap. Each clone has its own match limits, extra options and JIT
+/// stack assignment, and can be sent to another task to match concurrently.
 pub struct Pcre {
 
-    priv code: *detail::pcre,
+    priv code: Arc<CompiledCode>,
 
-    priv extra: *mut detail::pcre_extra,
+    priv extra: Arc<StudyData>,
 
     priv capture_count_: c_int,
 
-    // a spot to place any matched marks, but is not thread safe? 
-    priv mark : *mut c_uchar
+    /// The name-to-number translation table, shared with every `Match` of this expression.
+    /// The entries are sorted by name, so that a name can be found with a binary search.
+    priv name_table_: Arc<~[(~str, ~[uint])]>,
+
+    /// Whether the regular expression was compiled in UTF-8 mode.
+    priv utf8: bool,
+
+    priv limits: MatchLimits,
+
+    /// Whether the regular expression was successfully JIT-compiled by the last call to
+    /// `study_with_options()`.
+    priv jit: bool,
+
+    /// The JIT stack used by the JIT-compiled code.
+    priv jit_stack: JitStackAssignment,
+
+    /// The flags set by `set_extra_options()`, applied to the copy of the study data made
+    /// for each match attempt.
+    priv extra_options: c_ulong,
+
+    /// The character tables set by `set_tables()`.
+    priv match_tables: Option<Arc<CharTables>>
+
+}
+
+/// A stack for JIT-compiled code, allocated by `pcre_jit_stack_alloc()`.
+///
+/// By default, JIT-compiled code uses 32K of the machine stack, which is too small for
+/// some patterns and subjects: matching then fails with `JitStackLimitError`. A `JitStack`
+/// grows on demand up to its maximum size. A JIT stack must not be used by more than one
+/// match attempt at a time.
+///
+/// See [assign_jit_stack()](struct.Pcre.html#method.assign_jit_stack).
+pub struct JitStack {
+
+    priv stack: *mut detail::pcre_jit_stack
 
 }
 
+/// Character tables built by `pcre_maketables()` from the character types and case
+/// conversions of a locale.
+///
+/// The tables decide which characters with values below 256 match `\w`, `\d` and `\s`,
+/// and how they match caselessly. In UTF-8 mode, characters above 127 are handled with
+/// Unicode properties instead, so the tables are mostly useful for matching text in 8-bit
+/// encodings such as ISO-8859-1 or ISO-8859-9 with
+/// [compile_bytes_with_tables()](struct.Pcre.html#method.compile_bytes_with_tables).
+///
+/// A `CharTables` is shared through an `Arc`, and every regular expression compiled with
+/// it keeps it alive.
+pub struct CharTables {
+
+    priv tables: *c_uchar
+
+}
+
+/// The JIT stack used by the JIT-compiled code of a regular expression.
+#[deriving(Clone)]
+enum JitStackAssignment {
+    /// The default 32K of the machine stack.
+    MachineJitStack,
+    /// A stack assigned by `assign_jit_stack()`, which is passed to `pcre_jit_exec()` by
+    /// each match attempt.
+    AssignedJitStack(Arc<JitStack>),
+    /// The stack of the task running the match attempt, set by `JitStack::set_for_task()`.
+    TaskJitStack
+}
+
 /// Represents a match of a subject string against a regular expression.
 pub struct Match<'a> {
 
@@ -125,31 +365,553 @@ pub struct Match<'a> {
 
     priv string_count_: c_int,
 
-    // TODO make this a private field and implement a get for it?
-    mark : Option<~str>
+    priv name_table: Arc<~[(~str, ~[uint])]>,
+
+    /// The name of the last `(*MARK)` passed on the matching path, if any.
+    priv mark: Option<~str>
+}
+
+/// Scratch space for match attempts of a regular expression, holding the offset vector
+/// filled in by libpcre and the mark of the last match attempt.
+///
+/// A `MatchData` can be reused for any number of match attempts. Unlike a `Match`, it also
+/// reports the mark of failed and partial matches. [exec_into()](struct.Pcre.html#method.exec_into)
+/// leaves the capture group offsets of a match in the `MatchData` itself, so that matching
+/// in a loop does not allocate.
+///
+/// See [exec_with_match_data()](struct.Pcre.html#method.exec_with_match_data).
+pub struct MatchData {
+
+    priv ovector: ~[c_int],
+
+    priv capture_count: c_int,
+
+    /// The number of substrings captured by the last match attempt, or 0 if it did not
+    /// match.
+    priv string_count_: c_int,
+
+    priv mark: Option<~str>
+
+}
+
+/// Represents a match of a subject byte string against a regular expression.
+///
+/// See [exec_bytes()](struct.Pcre.html#method.exec_bytes).
+pub struct BytesMatch<'a> {
+
+    priv subject: &'a [u8],
+
+    priv partial_ovector: ~[c_int],
+
+    priv string_count_: c_int,
+
+    priv name_table: Arc<~[(~str, ~[uint])]>
+
+}
+
+/// Error returned when a subject byte string is not valid UTF-8.
+#[deriving(Clone, Eq)]
+pub struct Utf8Error {
+
+    /// Either `PCRE_ERROR_BADUTF8` or `PCRE_ERROR_SHORTUTF8`.
+    priv code: c_int,
+
+    priv offset: uint,
+
+    priv reason: c_int
+
+}
+
+/// Information about a compiled regular expression, returned by
+/// [info()](struct.Pcre.html#method.info).
+///
+/// See the libpcre manpages, `man 3 pcre_fullinfo`, for more information about each field.
+#[deriving(Clone)]
+pub struct PatternInfo {
+
+    /// The compile options, including any set at the start of the pattern such as `(?i)`.
+    options: EnumSet<CompileOption>,
+
+    /// The size in bytes of the compiled pattern (PCRE_INFO_SIZE).
+    size: uint,
+
+    /// The size in bytes of the study data, or 0 if the pattern has not been studied
+    /// (PCRE_INFO_STUDYSIZE).
+    study_size: uint,
+
+    /// The number of capture groups (PCRE_INFO_CAPTURECOUNT).
+    capture_count: uint,
+
+    /// The number of the highest back reference, or 0 if there are none
+    /// (PCRE_INFO_BACKREFMAX).
+    backref_max: uint,
+
+    /// The character that every match must start with, if there is one
+    /// (PCRE_INFO_FIRSTCHARACTER).
+    first_character: Option<u32>,
+
+    /// Whether every match starts at the start of the subject or after a newline
+    /// (PCRE_INFO_FIRSTCHARACTERFLAGS).
+    starts_at_newline: bool,
+
+    /// A 256-bit table of the bytes that a match can start with, found by studying the
+    /// pattern (PCRE_INFO_FIRSTTABLE). Bit `b % 8` of byte `b / 8` is set for byte `b`.
+    first_table: Option<~[u8]>,
+
+    /// The last literal character that every match must contain, if there is one
+    /// (PCRE_INFO_REQUIREDCHAR).
+    required_character: Option<u32>,
+
+    /// The minimum length in characters of a match, or `None` if the pattern has not been
+    /// studied (PCRE_INFO_MINLENGTH).
+    min_length: Option<uint>,
+
+    /// The number of characters of the longest lookbehind assertion
+    /// (PCRE_INFO_MAXLOOKBEHIND).
+    max_lookbehind: uint,
+
+    /// Whether the pattern is anchored, either by the `Anchored` option or because every
+    /// alternative starts with an anchor such as `^`.
+    anchored: bool,
+
+    /// Whether the pattern can match the empty string, or `None` if libpcre is older than
+    /// 8.34 (PCRE_INFO_MATCH_EMPTY).
+    can_match_empty: Option<bool>,
+
+    /// Whether the pattern contains explicit CR or LF matches (PCRE_INFO_HASCRORLF).
+    has_cr_or_lf: bool,
+
+    /// The size in bytes of the JIT-compiled code (PCRE_INFO_JITSIZE).
+    jit_size: uint,
+
+    /// Whether partial matching works for the pattern. It does not work for some patterns
+    /// in versions of libpcre older than 8.00 (PCRE_INFO_OKPARTIAL).
+    ok_partial: bool,
+
+    /// The match limit set by `(*LIMIT_MATCH=d)` at the start of the pattern, if any
+    /// (PCRE_INFO_MATCHLIMIT).
+    match_limit: Option<uint>,
+
+    /// The recursion limit set by `(*LIMIT_RECURSION=d)` at the start of the pattern, if
+    /// any (PCRE_INFO_RECURSIONLIMIT).
+    match_limit_recursion: Option<uint>
+
+}
+
+/// The features and defaults that libpcre was built with, returned by
+/// [build_config()](fn.build_config.html).
+///
+/// See the libpcre manpages, `man 3 pcre_config`, for more information about each field.
+#[deriving(Clone)]
+pub struct BuildConfig {
+
+    /// Whether UTF-8 is supported (PCRE_CONFIG_UTF8).
+    utf8: bool,
+
+    /// Whether UTF-16 is supported, or `None` if unknown: only the 16-bit library, which
+    /// this crate does not use, reports it (PCRE_CONFIG_UTF16).
+    utf16: Option<bool>,
+
+    /// Whether UTF-32 is supported, or `None` if unknown: only the 32-bit library, which
+    /// this crate does not use, reports it (PCRE_CONFIG_UTF32).
+    utf32: Option<bool>,
+
+    /// Whether Unicode character properties such as `\p{L}` are supported
+    /// (PCRE_CONFIG_UNICODE_PROPERTIES).
+    unicode_properties: bool,
+
+    /// Whether JIT compiling is available (PCRE_CONFIG_JIT).
+    jit: bool,
+
+    /// The architecture targeted by the JIT compiler, or `None` if JIT compiling is not
+    /// available (PCRE_CONFIG_JITTARGET).
+    jit_target: Option<~str>,
+
+    /// The default newline convention: one of `NewlineCR`, `NewlineLF`, `NewlineCRLF`,
+    /// `NewlineAny` and `NewlineAnyCRLF` (PCRE_CONFIG_NEWLINE).
+    newline: CompileOption,
+
+    /// What `\R` matches by default: either `BsrUnicode` or `BsrAnyCRLF`
+    /// (PCRE_CONFIG_BSR).
+    bsr: CompileOption,
+
+    /// The number of bytes used for internal offsets in compiled patterns
+    /// (PCRE_CONFIG_LINK_SIZE).
+    link_size: uint,
+
+    /// The maximum number of nested parentheses in a pattern, or `None` if libpcre is older
+    /// than 8.34 (PCRE_CONFIG_PARENS_LIMIT).
+    parens_nest_limit: Option<uint>,
+
+    /// The default match limit (PCRE_CONFIG_MATCH_LIMIT).
+    match_limit: uint,
+
+    /// The default recursion limit (PCRE_CONFIG_MATCH_LIMIT_RECURSION).
+    match_limit_recursion: uint,
+
+    /// Whether the recursion of libpcre's internal `match()` function uses the machine
+    /// stack, rather than the heap (PCRE_CONFIG_STACKRECURSE).
+    stack_recursion: bool
+
+}
+
+/// Error returned when `pcre_fullinfo()` fails.
+#[deriving(Clone, Eq)]
+pub struct InfoError {
+
+    priv code: c_int
+
+}
+
+/// Error returned when `Pcre::load()` fails.
+pub enum LoadError {
+    /// The bytes were not returned by `Pcre::save()`, or were truncated or corrupted.
+    MalformedSaveError(&'static str),
+    /// The saved regular expression had to be recompiled, which failed.
+    RecompileError(CompilationError),
+    /// The recompiled regular expression could not be studied again.
+    RestudyError(~str)
+}
+
+/// The result of a match attempt which allows partial matches.
+///
+/// See [exec_partial()](struct.Pcre.html#method.exec_partial).
+pub enum PartialMatchResult<'a> {
+    /// The regular expression matched.
+    Full(Match<'a>),
+    /// The end of the subject string was reached before the regular expression could
+    /// match, but the characters from `start` to `end` match its beginning. The match
+    /// might succeed if more characters were added to the subject.
+    Partial {
+        start: uint,
+        end: uint
+    },
+    /// The regular expression did not match, and adding characters to the subject string
+    /// would not make it match.
+    NoMatch
+}
+
+/// Error returned when libpcre fails to complete a match attempt.
+///
+/// Not finding a match is not an error.
+#[deriving(Clone, Eq)]
+pub enum ExecError {
+    /// The match limit was reached (PCRE_ERROR_MATCHLIMIT).
+    MatchLimitError,
+    /// The recursion limit was reached (PCRE_ERROR_RECURSIONLIMIT).
+    RecursionLimitError,
+    /// The start offset was negative or greater than the length of the subject
+    /// (PCRE_ERROR_BADOFFSET).
+    BadOffsetError,
+    /// The start offset did not point to the beginning of a UTF-8 character
+    /// (PCRE_ERROR_BADUTF8_OFFSET).
+    BadUtf8OffsetError,
+    /// The subject was not valid UTF-8 (PCRE_ERROR_BADUTF8 or PCRE_ERROR_SHORTUTF8).
+    BadUtf8Error(Utf8Error),
+    /// The JIT stack was too small (PCRE_ERROR_JIT_STACKLIMIT).
+    JitStackLimitError,
+    /// libpcre failed to allocate memory (PCRE_ERROR_NOMEMORY).
+    NoMemoryError,
+    /// libpcre encountered an internal error (PCRE_ERROR_INTERNAL).
+    InternalError,
+    /// A callout function returned `CalloutAbort` (PCRE_ERROR_CALLOUT).
+    CalloutAbortedError,
+    /// A partial match was found by a method that only reports complete matches
+    /// (PCRE_ERROR_PARTIAL). Use [exec_partial()](struct.Pcre.html#method.exec_partial)
+    /// to get the offsets of partial matches.
+    PartialMatchError,
+    /// The workspace of a `DfaMatcher` was too small (PCRE_ERROR_DFA_WSSIZE).
+    DfaWorkspaceSizeError,
+    /// A DFA match attempt was restarted after a match attempt which did not end in a
+    /// partial match, or with a different workspace (PCRE_ERROR_DFA_BADRESTART).
+    DfaBadRestartError,
+    /// The regular expression was not JIT-compiled for the partial matching mode
+    /// requested (PCRE_ERROR_JIT_BADOPTION).
+    JitBadOptionError,
+    /// A regular expression compiled with `compile_bytes()` was matched against a `&str`
+    /// subject, where a match could end inside a multibyte character. Use `exec_bytes()`
+    /// instead.
+    NonUtf8PatternError,
+    /// Any other libpcre error, holding the raw error code.
+    OtherExecError(int)
 }
 
 /// Iterator type for iterating matches within a subject string.
+///
+/// If a match attempt fails with an error, then iteration stops and the error is available
+/// from [error()](struct.MatchIterator.html#method.error). Use
+/// [try_next()](struct.MatchIterator.html#method.try_next) to handle errors as they occur.
 pub struct MatchIterator<'a> {
 
-    priv code: *detail::pcre,
+    priv code: Arc<CompiledCode>,
 
-    priv extra: *detail::pcre_extra,
+    priv extra: Arc<StudyData>,
 
     priv capture_count: c_int,
 
-    priv subject: &'a str,
+    priv name_table: Arc<~[(~str, ~[uint])]>,
 
-    /// The subject string as a `CString`. In MatchIterator's next() method, this is re-used
-    /// each time so that only one C-string copy of the subject string needs to be allocated.
-    priv subject_cstring: c_str::CString,
+    priv subject: &'a str,
 
     priv offset: c_int,
 
     priv options: EnumSet<ExecOption>,
 
-    priv ovector: ~[c_int]
+    priv limits: MatchLimits,
+
+    priv match_tables: Option<Arc<CharTables>>,
+
+    priv jit: bool,
+
+    priv jit_stack: JitStackAssignment,
+
+    priv ovector: ~[c_int],
+
+    /// Whether the last match was empty, in which case the next match attempt at the same
+    /// offset must not match the empty string again.
+    priv last_match_empty: bool,
+
+    /// Whether CRLF is a newline, in which case an empty match before a CRLF is followed by
+    /// a match attempt after the LF rather than between the CR and the LF.
+    priv crlf_is_newline: bool,
+
+    /// The error which ended the iteration, if any.
+    priv error: Option<ExecError>
+
+}
+
+/// Iterator type for splitting a subject string around the matches of a regular expression.
+///
+/// See [split()](struct.Pcre.html#method.split) for the splitting rules.
+pub struct SplitIterator<'a> {
+
+    priv matches: MatchIterator<'a>,
+
+    priv subject: &'a str,
+
+    /// The maximum number of fields to produce, not counting captured separators, or zero
+    /// for no limit.
+    priv limit: uint,
+
+    /// The number of fields produced so far, not counting captured separators.
+    priv field_count: uint,
+
+    /// The end index of the previous separator.
+    priv last_end: uint,
+
+    /// Fields and captured separators which have been found but not yet returned.
+    priv queue: ~[&'a str],
+
+    /// The number of empty items held back because they might be trailing empty fields.
+    priv pending_empty: uint,
+
+    priv finished: bool
+
+}
+
+/// Iterator type for iterating the capture groups of a `Match`, starting with group 0.
+///
+/// Each item is the substring for a capture group, or `None` if the group did not
+/// participate in the match.
+pub struct CaptureIterator<'m, 'a> {
+
+    priv m: &'m Match<'a>,
+
+    priv n: uint
+
+}
+
+/// Iterator type for iterating the named capture groups of a `Match`.
+///
+/// Each item is the name of a capture group and its substring, or `None` if no group of
+/// that name participated in the match.
+pub struct NamedGroups<'m, 'a> {
+
+    priv m: &'m Match<'a>,
+
+    priv names: vec::Items<'m, (~str, ~[uint])>
+
+}
+
+/// Matcher which uses libpcre's alternative matching algorithm, `pcre_dfa_exec()`.
+///
+/// The DFA algorithm scans the subject once and finds every match that starts at the
+/// leftmost matching position, but it does not set capture groups. See the libpcre
+/// manpages, `man 3 pcrematching`, for more information.
+pub struct DfaMatcher<'p> {
+
+    priv re: &'p Pcre,
+
+    /// The workspace of `pcre_dfa_exec()`. It is kept between match attempts so that a
+    /// partial match can be continued by [restart()](struct.DfaMatcher.html#method.restart).
+    priv workspace: ~[c_int],
+
+    priv shortest: bool,
+
+    priv max_matches: uint
+
+}
+
+/// Represents the matches found by a `DfaMatcher` at the leftmost matching position.
+///
+/// The matches all start at the same offset and are ordered longest first.
+pub struct DfaMatch<'a> {
+
+    priv subject: &'a str,
+
+    priv ovector: ~[c_int],
+
+    priv count: uint
+
+}
+
+/// The result of a `DfaMatcher` match attempt.
+pub enum DfaResult<'a> {
+    /// The regular expression matched.
+    DfaFull(DfaMatch<'a>),
+    /// A partial match was found from `start` to `end`. Only reported if `ExecPartialSoft`
+    /// or `ExecPartialHard` was given.
+    DfaPartial {
+        start: uint,
+        end: uint
+    },
+    /// The regular expression did not match.
+    DfaNoMatch
+}
+
+/// A set of regular expressions which are matched against a subject string together,
+/// reporting every regular expression that matches.
+///
+/// Regular expressions which can be combined are compiled into one alternation, so the
+/// subject string is scanned once for up to 128 of them rather than once for each. The
+/// others, such as those with backreferences, subroutine calls, named groups or
+/// backtracking control verbs, are matched one at a time.
+pub struct PcreSet {
+
+    priv chunks: ~[SetChunk],
+
+    /// The regular expressions which could not be combined, with their indices.
+    priv separate: ~[(uint, Pcre)],
+
+    priv len: uint
+
+}
+
+/// Regular expressions of a `PcreSet` combined into one. Alternative `k` is
+/// `(?C2k)(?>(?:pattern\E))(?C2k+1)` and stands for the regular expression `indices[k]`.
+struct SetChunk {
+    re: Pcre,
+    indices: ~[uint]
+}
+
+/// A regular expression of a `PcreSet` which matched a subject string.
+#[deriving(Clone, Eq)]
+pub struct SetMatch {
+
+    /// The index of the regular expression in the set.
+    index: uint,
+
+    /// The start offset of the leftmost match.
+    start: uint,
+
+    /// The end offset of the leftmost match.
+    end: uint
+
+}
+
+/// Error returned when a regular expression of a `PcreSet` fails to compile.
+pub struct SetCompilationError {
+
+    priv index: uint,
+
+    priv error: CompilationError
+
+}
+
+/// A bounded cache of compiled regular expressions, keyed by the pattern and its compile
+/// and study options.
+///
+/// The least recently used regular expression is evicted when the cache is full. Clones of
+/// a `PatternCache` share the same cache, which can be used from several tasks at once.
+#[deriving(Clone)]
+pub struct PatternCache {
+
+    priv state: MutexArc<CacheState>
+
+}
+
+struct CacheState {
+    cache: LruCache<CacheKey, Pcre>,
+    stats: CacheStats
+}
+
+#[deriving(Eq, Hash)]
+struct CacheKey {
+    pattern: ~str,
+    compile_options: c_ulong,
+    /// The study options, or `None` if the regular expression is not studied.
+    study_options: Option<uint>
+}
+
+/// Statistics of a `PatternCache`.
+#[deriving(Clone, Eq, Show)]
+pub struct CacheStats {
+
+    /// The number of lookups which found the regular expression in the cache.
+    hits: uint,
 
+    /// The number of lookups which had to compile the regular expression.
+    misses: uint,
+
+    /// The number of regular expressions evicted to make room for others.
+    evictions: uint
+
+}
+
+/// Builds a `Pcre` from a pattern and its compile, study and match settings.
+///
+/// ```notrust
+/// let re = PcreBuilder::new("^hello$").flags("im").jit().match_limit(10000).build();
+/// ```
+pub struct PcreBuilder {
+
+    priv pattern: ~str,
+
+    priv options: EnumSet<CompileOption>,
+
+    /// The study options, or `None` if the regular expression is not to be studied.
+    priv study_options: Option<EnumSet<StudyOption>>,
+
+    priv limits: MatchLimits,
+
+    priv extra_options: EnumSet<ExtraOption>,
+
+    priv tables: Option<Arc<CharTables>>,
+
+    /// The first unknown flag passed to `flags()`, reported by `build()`.
+    priv bad_flag: Option<char>
+
+}
+
+/// Error returned by [PcreBuilder::build()](struct.PcreBuilder.html#method.build).
+pub enum BuildError {
+    /// `flags()` was given a character which is not a Perl flag.
+    UnknownFlagError(char),
+    /// The regular expression failed to compile.
+    CompileError(CompilationError),
+    /// libpcre failed to study the regular expression.
+    StudyError(~str)
+}
+
+/// A source of replacement text for [replace()](struct.Pcre.html#method.replace),
+/// [replace_n()](struct.Pcre.html#method.replace_n) and
+/// [replace_all()](struct.Pcre.html#method.replace_all).
+///
+/// This trait is implemented for template strings and for closures that take a `&Match`
+/// and return the replacement as an owned string.
+pub trait Replacer {
+    /// Returns the replacement text for the match `m`.
+    fn reg_replace<'r>(&'r mut self, m: &Match) -> MaybeOwned<'r>;
 }
 
 impl CLike for CompileOption {
@@ -306,6 +1068,72 @@ impl CompilationError {
     pub fn offset(&self) -> uint {
         self.erroffset as uint
     }
+
+    /// Returns the libpcre error code, from 1 to 86.
+    pub fn code(&self) -> int {
+        self.code as int
+    }
+
+    /// Returns the kind of error, as given by the libpcre error code.
+    pub fn kind(&self) -> CompileErrorKind {
+        compile_error_kind(self.code)
+    }
+
+    /// Returns a suggested fix for common mistakes, such as an unescaped `[`, `{`, `(` or
+    /// `\` in literal text.
+    pub fn suggestion(&self) -> Option<&'static str> {
+        match self.kind() {
+            MissingBracketError => Some("to match a literal `[`, escape it as `\\[`; otherwise close the character class with `]`"),
+            QuantifierOutOfOrderError | QuantifierTooBigError => Some("to match a literal `{`, escape it as `\\{`"),
+            NothingToRepeatError => Some("to match a literal `*`, `+`, `?` or `{`, escape it with a backslash, as in `\\*`"),
+            BackslashAtEndError | BackslashCAtEndError | UnrecognizedEscapeError | UnsupportedEscapeError
+                | MalformedBackslashGError | MalformedBackslashKError | MissingOctalBraceError => Some("to match a literal backslash, escape it as `\\\\`"),
+            MissingParenthesisError | UnmatchedParenthesesError => Some("to match a literal parenthesis, escape it as `\\(` or `\\)`"),
+            PosixClassOutsideClassError => Some("put the POSIX class inside a character class, as in `[[:alpha:]]`"),
+            RangeOutOfOrderError | InvalidClassRangeError => Some("to match a literal `-` in a character class, put it first or last, or escape it as `\\-`"),
+            LookbehindNotFixedLengthError => Some("each alternative of a lookbehind assertion must match a fixed number of characters; `\\K` can be used instead for variable-length text"),
+            _ => None
+        }
+    }
+
+    /// Renders the error for display to the author of the pattern: the error message, the
+    /// line of the pattern containing the error with a caret under the error offset, and a
+    /// suggested fix, if any.
+    ///
+    /// ```notrust
+    /// compilation failed at offset 4: missing )
+    /// (abc
+    ///     ^
+    /// help: to match a literal parenthesis, escape it as `\(` or `\)`
+    /// ```
+    pub fn render(&self) -> ~str {
+        let offset = if (self.erroffset as uint) < self.pattern.len() { self.erroffset as uint } else { self.pattern.len() };
+        let line_start = match self.pattern.slice_to(offset).iter().rposition(|&b| b == '\n' as u8) {
+            None => 0u,
+            Some(i) => i + 1
+        };
+        let line_end = match self.pattern.slice_from(offset).iter().position(|&b| b == '\n' as u8) {
+            None => self.pattern.len(),
+            Some(i) => offset + i
+        };
+
+        let mut rendered = format!("{}\n", *self);
+        rendered.push_str(str::from_utf8_lossy(self.pattern.slice(line_start, line_end)).as_slice());
+        rendered.push_char('\n');
+        // Tabs are kept so that the caret lines up with the error.
+        for c in str::from_utf8_lossy(self.pattern.slice(line_start, offset)).as_slice().chars() {
+            rendered.push_char(if c == '\t' { '\t' } else { ' ' });
+        }
+        rendered.push_char('^');
+        match self.suggestion() {
+            None => (),
+            Some(suggestion) => {
+                rendered.push_str("\nhelp: ");
+                rendered.push_str(suggestion);
+            }
+        }
+        rendered
+    }
 }
 
 impl fmt::Show for CompilationError {
@@ -317,74 +1145,383 @@ impl fmt::Show for CompilationError {
     }
 }
 
-impl Pcre {
-    /// Compiles the given regular expression.
-    ///
-    /// # Argument
-    /// * `pattern` - The regular expression.
-    pub fn compile(pattern: &str) -> Result<Pcre, CompilationError> {
-        let no_options: EnumSet<CompileOption> = EnumSet::empty();
-        Pcre::compile_with_options(pattern, &no_options)
+impl Utf8Error {
+    /// Returns the libpcre error code, `PCRE_ERROR_BADUTF8` (-10) or
+    /// `PCRE_ERROR_SHORTUTF8` (-25).
+    pub fn code(&self) -> int {
+        self.code as int
     }
 
-    /// Compiles a regular expression using the given bitwise-OR'd options `options`.
-    ///
-    /// # Arguments
-    /// * `pattern` - The regular expression.
-    /// * `options` - Bitwise-OR'd compilation options. See the libpcre manpages,
-    ///   `man 3 pcre_compile`, for more information.
-    pub fn compile_with_options(pattern: &str, options: &EnumSet<CompileOption>) -> Result<Pcre, CompilationError> {
-        pattern.with_c_str(|pattern_c_str| {
-            unsafe {
-                // Use the default character tables.
-                let tableptr: *c_uchar = ptr::null();
-                match detail::pcre_compile(pattern_c_str, options, tableptr) {
-                    Err((opt_err, erroffset)) => Err(CompilationError {
-                        opt_err: opt_err,
-                        erroffset: erroffset
-                    }),
-                    Ok(mut_code) => {
-                        let code = mut_code as *detail::pcre;
-                        assert!(code.is_not_null());
-                        // Take a reference.
-                        detail::pcre_refcount(code as *mut detail::pcre, 1);
-
-                        let extra: *mut detail::pcre_extra = ptr::mut_null();
-
-                        let mut capture_count: c_int = 0;
-                        detail::pcre_fullinfo(code, extra as *detail::pcre_extra, detail::PCRE_INFO_CAPTURECOUNT, 
-                            &mut capture_count as *mut c_int as *mut c_void);
-
-                        Ok(Pcre {
-                            code: code,
-                            extra: extra,
-                            capture_count_: capture_count,
-                            mark : ptr::mut_null()
-                        })
-                    }
-                }
-            }
-        })
+    /// Returns the byte offset of the start of the invalid UTF-8 character.
+    pub fn offset(&self) -> uint {
+        self.offset
     }
 
-    /// Returns the number of capture groups in the regular expression, including one for
-    /// each named capture group.
-    ///
-    /// This count does not include "group 0", which is the full substring within a subject
-    /// string that matches the regular expression.
-    ///
-    /// # See also
-    /// * [name_count()](#fn.name_count) - Returns the number of named capture groups.
-    pub fn capture_count(&self) -> uint {
-        self.capture_count_ as uint
+    /// Returns libpcre's reason code, `PCRE_UTF8_ERR1` to `PCRE_UTF8_ERR22`. See the
+    /// libpcre manpages, `man 3 pcreunicode`, for the meaning of each code.
+    pub fn reason(&self) -> int {
+        self.reason as int
     }
 
-    /// Matches the compiled regular expression against a given subject string `subject`.
-    /// If no match is found, then `None` is returned. Otherwise, a `Match` object is returned
-    /// which provides access to the captured substrings as slices of the subject string.
-    ///
-    /// # Argument
-    /// * `subject` - The subject string.
+    /// Returns `true` if the subject ends with a truncated UTF-8 character.
+    pub fn is_truncated(&self) -> bool {
+        // PCRE_UTF8_ERR1 to PCRE_UTF8_ERR5 mean that bytes are missing at the end of the
+        // subject. With PCRE_PARTIAL_HARD, PCRE_ERROR_SHORTUTF8 is returned instead.
+        self.code == detail::PCRE_ERROR_SHORTUTF8 || (self.reason >= 1 && self.reason <= 5)
+    }
+}
+
+impl InfoError {
+    /// Returns the libpcre error code, for example -4 for PCRE_ERROR_BADMAGIC.
+    pub fn code(&self) -> int {
+        self.code as int
+    }
+}
+
+impl fmt::Show for InfoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.code == detail::PCRE_ERROR_BADMAGIC {
+            write!(f.buf, "not a compiled pattern")
+        } else if self.code == detail::PCRE_ERROR_BADENDIANNESS {
+            write!(f.buf, "pattern compiled with different endianness")
+        } else {
+            write!(f.buf, "pcre_fullinfo() failed with error code {:d}", self.code as int)
+        }
+    }
+}
+
+impl fmt::Show for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MalformedSaveError(reason) => write!(f.buf, "malformed saved regular expression: {:s}", reason),
+            RecompileError(ref err) => write!(f.buf, "recompiling saved regular expression: {}", *err),
+            RestudyError(ref err) => write!(f.buf, "studying saved regular expression: {:s}", *err)
+        }
+    }
+}
+
+impl fmt::Show for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UnknownFlagError(c) => write!(f.buf, "unknown flag '{:c}'", c),
+            CompileError(ref err) => write!(f.buf, "{}", *err),
+            StudyError(ref err) => write!(f.buf, "study failed: {:s}", *err)
+        }
+    }
+}
+
+impl fmt::Show for Utf8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f.buf, "invalid UTF-8 at offset {:u} (reason code {:d})", self.offset, self.reason as int)
+    }
+}
+
+impl<'a> CalloutBlock<'a> {
+    /// Returns the callout number: `n` for `(?Cn)`, zero for `(?C)`, or 255 for an
+    /// automatic callout.
+    pub fn callout_number(&self) -> uint {
+        self.block.callout_number as uint
+    }
+
+    /// Returns the current offset within the subject string.
+    pub fn current_position(&self) -> uint {
+        self.block.current_position as uint
+    }
+
+    /// Returns the offset within the subject string at which the current match attempt
+    /// started.
+    pub fn start_match(&self) -> uint {
+        self.block.start_match as uint
+    }
+
+    /// Returns one more than the number of the highest capture group that has been set
+    /// so far. This is always at least 1.
+    pub fn capture_top(&self) -> uint {
+        self.block.capture_top as uint
+    }
+
+    /// Returns the number of the most recently closed capture group, or `None` if no
+    /// group has been closed yet.
+    pub fn capture_last(&self) -> Option<uint> {
+        if self.block.capture_last < 0 {
+            None
+        } else {
+            Some(self.block.capture_last as uint)
+        }
+    }
+
+    /// Returns the start and end offsets of capture group `n` so far, or `None` if the
+    /// group has not been set.
+    pub fn group_range(&self, n: uint) -> Option<(uint, uint)> {
+        if n >= self.capture_top() {
+            return None;
+        }
+        unsafe {
+            let start = *self.block.offset_vector.offset((n * 2) as int);
+            let end = *self.block.offset_vector.offset((n * 2 + 1) as int);
+            if start < 0 || end < 0 {
+                None
+            } else {
+                Some((start as uint, end as uint))
+            }
+        }
+    }
+
+    /// Returns the offset within the pattern of the next item to be matched.
+    pub fn pattern_position(&self) -> uint {
+        self.block.pattern_position as uint
+    }
+
+    /// Returns the length of the next item to be matched in the pattern.
+    pub fn next_item_length(&self) -> uint {
+        self.block.next_item_length as uint
+    }
+
+    /// Returns the most recently passed `(*MARK)` name, if any.
+    pub fn mark(&self) -> Option<~str> {
+        // The `mark` field was added in version 2 of the callout block.
+        if self.block.version < 2 || self.block.mark.is_null() {
+            None
+        } else {
+            unsafe { Some(str::raw::from_c_str(self.block.mark as *c_char)) }
+        }
+    }
+}
+
+impl MatchLimits {
+    /// Creates a `MatchLimits` which leaves both limits unchanged.
+    pub fn new() -> MatchLimits {
+        MatchLimits {
+            match_limit: None,
+            match_limit_recursion: None
+        }
+    }
+
+    /// Returns these limits, with any limit that is `None` taken from `defaults`.
+    fn or(&self, defaults: &MatchLimits) -> MatchLimits {
+        MatchLimits {
+            match_limit: self.match_limit.or(defaults.match_limit),
+            match_limit_recursion: self.match_limit_recursion.or(defaults.match_limit_recursion)
+        }
+    }
+
+    /// Returns a copy of `extra`, or of an empty extra block if `extra` is null, with these
+    /// limits applied. The copy can be passed to `pcre_exec()` without modifying `extra`.
+    unsafe fn apply(&self, extra: *detail::pcre_extra) -> detail::pcre_extra {
+        let mut extra_copy = if extra.is_null() {
+            detail::pcre_extra::empty()
+        } else {
+            *extra
+        };
+        match self.match_limit {
+            None => (),
+            Some(limit) => {
+                extra_copy.flags |= detail::PCRE_EXTRA_MATCH_LIMIT;
+                extra_copy.match_limit = limit as c_ulong;
+            }
+        }
+        match self.match_limit_recursion {
+            None => (),
+            Some(limit) => {
+                extra_copy.flags |= detail::PCRE_EXTRA_MATCH_LIMIT_RECURSION;
+                extra_copy.match_limit_recursion = limit as c_ulong;
+            }
+        }
+        extra_copy
+    }
+}
+
+impl ExecError {
+    /// Returns the libpcre error code, for example -8 for PCRE_ERROR_MATCHLIMIT, or 0 for
+    /// `NonUtf8PatternError`, which is not reported by libpcre.
+    pub fn code(&self) -> int {
+        match *self {
+            MatchLimitError => detail::PCRE_ERROR_MATCHLIMIT as int,
+            RecursionLimitError => detail::PCRE_ERROR_RECURSIONLIMIT as int,
+            BadOffsetError => detail::PCRE_ERROR_BADOFFSET as int,
+            BadUtf8OffsetError => detail::PCRE_ERROR_BADUTF8_OFFSET as int,
+            BadUtf8Error(ref err) => err.code(),
+            JitStackLimitError => detail::PCRE_ERROR_JIT_STACKLIMIT as int,
+            NoMemoryError => detail::PCRE_ERROR_NOMEMORY as int,
+            InternalError => detail::PCRE_ERROR_INTERNAL as int,
+            CalloutAbortedError => detail::PCRE_ERROR_CALLOUT as int,
+            PartialMatchError => detail::PCRE_ERROR_PARTIAL as int,
+            DfaWorkspaceSizeError => detail::PCRE_ERROR_DFA_WSSIZE as int,
+            DfaBadRestartError => detail::PCRE_ERROR_DFA_BADRESTART as int,
+            JitBadOptionError => detail::PCRE_ERROR_JIT_BADOPTION as int,
+            NonUtf8PatternError => 0,
+            OtherExecError(code) => code
+        }
+    }
+}
+
+impl fmt::Show for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MatchLimitError => write!(f.buf, "match limit exceeded"),
+            RecursionLimitError => write!(f.buf, "recursion limit exceeded"),
+            BadOffsetError => write!(f.buf, "start offset out of range"),
+            BadUtf8OffsetError => write!(f.buf, "start offset is not at the beginning of a UTF-8 character"),
+            BadUtf8Error(ref err) => write!(f.buf, "{}", *err),
+            JitStackLimitError => write!(f.buf, "JIT stack limit exceeded"),
+            NoMemoryError => write!(f.buf, "out of memory"),
+            InternalError => write!(f.buf, "internal libpcre error"),
+            CalloutAbortedError => write!(f.buf, "match attempt abandoned by a callout"),
+            PartialMatchError => write!(f.buf, "partial match"),
+            DfaWorkspaceSizeError => write!(f.buf, "DFA workspace too small"),
+            DfaBadRestartError => write!(f.buf, "DFA match attempt cannot be restarted"),
+            JitBadOptionError => write!(f.buf, "matching mode not JIT-compiled"),
+            NonUtf8PatternError => write!(f.buf, "non-UTF-8 regular expression matched against a string"),
+            OtherExecError(code) => write!(f.buf, "pcre_exec() failed with error code {:d}", code)
+        }
+    }
+}
+
+impl Pcre {
+    /// Compiles the given regular expression.
+    ///
+    /// # Argument
+    /// * `pattern` - The regular expression.
+    pub fn compile(pattern: &str) -> Result<Pcre, CompilationError> {
+        let no_options: EnumSet<CompileOption> = EnumSet::empty();
+        Pcre::compile_with_options(pattern, &no_options)
+    }
+
+    /// Compiles a regular expression using the given bitwise-OR'd options `options`.
+    ///
+    /// # Arguments
+    /// * `pattern` - The regular expression.
+    /// * `options` - Bitwise-OR'd compilation options. See the libpcre manpages,
+    ///   `man 3 pcre_compile`, for more information.
+    pub fn compile_with_options(pattern: &str, options: &EnumSet<CompileOption>) -> Result<Pcre, CompilationError> {
+        Pcre::compile_internal(pattern.as_bytes(), options, true, None)
+    }
+
+    /// Compiles the given regular expression using the given bitwise-OR'd options `options`
+    /// and the character tables `tables` instead of libpcre's default tables.
+    ///
+    /// # Arguments
+    /// * `pattern` - The regular expression.
+    /// * `options` - Bitwise-OR'd compilation options. See the libpcre manpages,
+    ///   `man 3 pcre_compile`, for more information.
+    /// * `tables` - The character tables, which are kept alive for as long as the compiled
+    ///   regular expression.
+    pub fn compile_with_tables(pattern: &str, options: &EnumSet<CompileOption>, tables: Arc<CharTables>) -> Result<Pcre, CompilationError> {
+        Pcre::compile_internal(pattern.as_bytes(), options, true, Some(tables))
+    }
+
+    /// Compiles the given regular expression for matching byte strings which need not be
+    /// valid UTF-8.
+    ///
+    /// The pattern is compiled without UTF-8 mode, so each byte of a subject string is
+    /// treated as one character and the pattern itself may contain arbitrary bytes other
+    /// than NUL.
+    ///
+    /// Regular expressions compiled this way must be matched with the
+    /// [exec_bytes()](#method.exec_bytes) family of methods. Matching them against a `&str`
+    /// subject returns `NonUtf8PatternError`, since a match could begin or end within a
+    /// multibyte character.
+    ///
+    /// # Argument
+    /// * `pattern` - The regular expression.
+    pub fn compile_bytes(pattern: &[u8]) -> Result<Pcre, CompilationError> {
+        let no_options: EnumSet<CompileOption> = EnumSet::empty();
+        Pcre::compile_bytes_with_options(pattern, &no_options)
+    }
+
+    /// Compiles the given regular expression without UTF-8 mode using the given
+    /// bitwise-OR'd options `options`. See [compile_bytes()](#method.compile_bytes).
+    ///
+    /// # Arguments
+    /// * `pattern` - The regular expression.
+    /// * `options` - Bitwise-OR'd compilation options. See the libpcre manpages,
+    ///   `man 3 pcre_compile`, for more information.
+    pub fn compile_bytes_with_options(pattern: &[u8], options: &EnumSet<CompileOption>) -> Result<Pcre, CompilationError> {
+        Pcre::compile_internal(pattern, options, false, None)
+    }
+
+    /// Compiles the given regular expression without UTF-8 mode using the given
+    /// bitwise-OR'd options `options` and the character tables `tables`. See
+    /// [compile_bytes()](#method.compile_bytes) and [CharTables](struct.CharTables.html).
+    ///
+    /// # Arguments
+    /// * `pattern` - The regular expression.
+    /// * `options` - Bitwise-OR'd compilation options. See the libpcre manpages,
+    ///   `man 3 pcre_compile`, for more information.
+    /// * `tables` - The character tables, which are kept alive for as long as the compiled
+    ///   regular expression.
+    pub fn compile_bytes_with_tables(pattern: &[u8], options: &EnumSet<CompileOption>, tables: Arc<CharTables>) -> Result<Pcre, CompilationError> {
+        Pcre::compile_internal(pattern, options, false, Some(tables))
+    }
+
+    fn compile_internal(pattern: &[u8], options: &EnumSet<CompileOption>, utf8: bool, tables: Option<Arc<CharTables>>) -> Result<Pcre, CompilationError> {
+        // A null table pointer selects the default character tables.
+        let tableptr: *c_uchar = match tables {
+            None => ptr::null(),
+            Some(ref tables) => tables.tables
+        };
+        let result = pattern.with_c_str(|pattern_c_str| unsafe {
+            detail::pcre_compile2(pattern_c_str, options, utf8, tableptr)
+        });
+        match result {
+            Err((code, opt_err, erroffset)) => Err(CompilationError {
+                code: code,
+                opt_err: opt_err,
+                erroffset: erroffset,
+                pattern: pattern.to_owned()
+            }),
+            Ok(mut_code) => unsafe {
+                Ok(Pcre::from_code(mut_code as *detail::pcre, ptr::mut_null(), None, pattern.to_owned(), options.clone(), utf8, tables))
+            }
+        }
+    }
+
+    /// Takes ownership of the compiled regular expression `code` and its study data `extra`,
+    /// which may be null, made with the study options `study_options`.
+    unsafe fn from_code(code: *detail::pcre, extra: *mut detail::pcre_extra, study_options: Option<EnumSet<StudyOption>>, pattern: ~[u8], options: EnumSet<CompileOption>, utf8: bool, tables: Option<Arc<CharTables>>) -> Pcre {
+        assert!(code.is_not_null());
+
+        let capture_count: c_int = fullinfo(code, ptr::null(), detail::PCRE_INFO_CAPTURECOUNT, 0).unwrap();
+
+        Pcre {
+            code: Arc::new(CompiledCode {
+                ptr: code,
+                tables: tables,
+                pattern: pattern,
+                options: options
+            }),
+            extra: Arc::new(StudyData { ptr: extra, options: study_options }),
+            capture_count_: capture_count,
+            name_table_: Arc::new(read_name_table(code)),
+            utf8: utf8,
+            limits: MatchLimits::new(),
+            jit: false,
+            jit_stack: MachineJitStack,
+            extra_options: 0,
+            match_tables: None
+        }
+    }
+
+    /// Returns the number of capture groups in the regular expression, including one for
+    /// each named capture group.
+    ///
+    /// This count does not include "group 0", which is the full substring within a subject
+    /// string that matches the regular expression.
+    ///
+    /// # See also
+    /// * [name_count()](#fn.name_count) - Returns the number of named capture groups.
+    pub fn capture_count(&self) -> uint {
+        self.capture_count_ as uint
+    }
+
+    /// Matches the compiled regular expression against a given subject string `subject`.
+    /// If no match is found, then `Ok(None)` is returned. Otherwise, a `Match` object is
+    /// returned which provides access to the captured substrings as slices of the subject
+    /// string. If libpcre fails to complete the match attempt, for example because the
+    /// match limit was reached, then an `ExecError` is returned.
+    ///
+    /// # Argument
+    /// * `subject` - The subject string.
     ///
     /// # Performance notes
     /// This method is intended to be used to find individual matches. If multiple matches
@@ -393,14 +1530,15 @@ impl Pcre {
     /// If a regular expression will be used often, it might be worth studying it to possibly
     /// speed up matching. See the [study()](#fn.study) method.
     #[inline]
-    pub fn exec<'a>(&mut self, subject: &'a str) -> Option<Match<'a>> {
+    pub fn exec<'a>(&self, subject: &'a str) -> Result<Option<Match<'a>>, ExecError> {
         self.exec_from(subject, 0)
     }
 
     /// Matches the compiled regular expression against a given subject string `subject`
     /// starting at offset `startoffset` within the subject string. If no match is found,
-    /// then `None` is returned. Otherwise, a `Match` object is returned which provides
-    /// access to the captured substrings as slices of the subject string.
+    /// then `Ok(None)` is returned. Otherwise, a `Match` object is returned which provides
+    /// access to the captured substrings as slices of the subject string. If libpcre fails
+    /// to complete the match attempt, then an `ExecError` is returned.
     ///
     /// # Arguments
     /// * `subject` - The subject string.
@@ -414,16 +1552,17 @@ impl Pcre {
     /// If a regular expression will be used often, it might be worth studying it to possibly
     /// speed up matching. See the [study()](#fn.study) method.
     #[inline]
-    pub fn exec_from<'a>(&mut self, subject: &'a str, startoffset: uint) -> Option<Match<'a>> {
+    pub fn exec_from<'a>(&self, subject: &'a str, startoffset: uint) -> Result<Option<Match<'a>>, ExecError> {
         let no_options: EnumSet<ExecOption> = EnumSet::empty();
         self.exec_from_with_options(subject, startoffset, &no_options)
     }
 
     /// Matches the compiled regular expression against a given subject string `subject`
     /// starting at offset `startoffset` within the subject string and using the given
-    /// bitwise-OR'd matching options `options`. If no match is found, then `None` is
+    /// bitwise-OR'd matching options `options`. If no match is found, then `Ok(None)` is
     /// returned. Otherwise, a `Match` object is returned which provides access to the
-    /// captured substrings as slices of the subject string.
+    /// captured substrings as slices of the subject string. If libpcre fails to complete
+    /// the match attempt, then an `ExecError` is returned.
     ///
     /// # Arguments
     /// * `subject` - The subject string.
@@ -439,307 +1578,2958 @@ impl Pcre {
     /// If a regular expression will be used often, it might be worth studying it to possibly
     /// speed up matching. See the [study()](#fn.study) method.
     #[inline]
-    pub fn exec_from_with_options<'a>(&mut self, subject: &'a str, startoffset: uint, options: &EnumSet<ExecOption>) -> Option<Match<'a>> {
-        let ovecsize = (self.capture_count_ + 1) * 3;
-        let mut ovector: ~[c_int] = vec::from_elem(ovecsize as uint, 0 as c_int);
+    pub fn exec_from_with_options<'a>(&self, subject: &'a str, startoffset: uint, options: &EnumSet<ExecOption>) -> Result<Option<Match<'a>>, ExecError> {
+        self.exec_from_with_limits(subject, startoffset, options, &MatchLimits::new())
+    }
 
-        unsafe {
-            subject.with_c_str_unchecked(|subject_c_str| -> Option<Match<'a>> {
-                // Update the mark location if it has been set in the ExtraOptions 
-                // in case this Pcre has been moved
-                if self.extra.is_not_null() && (*self.extra).mark.is_not_null() {
-                    (*self.extra).mark = &mut self.mark as *mut *mut u8;
-                }
-                let rc = detail::pcre_exec(self.code, self.extra as *detail::pcre_extra, subject_c_str, subject.len() as c_int, startoffset as c_int, options, ovector.as_mut_ptr(), ovecsize as c_int);
-                if rc >= 0 {
-                    let mark = if self.mark.is_not_null() {
-                        Some(std::str::raw::from_c_str(self.mark as *i8))
-                    } else {
-                        None
-                    };
-                    Some(Match {
-                        subject: subject,
-                        partial_ovector: ovector.slice_to(((self.capture_count_ + 1) * 2) as uint).to_owned(),
-                        string_count_: rc,
-                        mark: mark
-                    })
-                } else {
-                    None
-                }
-            })
-        }
+    /// Matches the compiled regular expression against a given subject string `subject`
+    /// like [exec_from_with_options()](#method.exec_from_with_options), overriding the
+    /// match limits of the regular expression for this call only.
+    ///
+    /// This can be used to bound the time spent on catastrophic backtracking when matching
+    /// untrusted input. If a limit is reached, then `MatchLimitError` or
+    /// `RecursionLimitError` is returned.
+    ///
+    /// # Arguments
+    /// * `subject` - The subject string.
+    /// * `startoffset` - Starting offset within `subject` at which to begin looking for
+    ///   a match.
+    /// * `options` - Bitwise-OR'd matching options. See the libpcre manpages, `man 3 pcre_exec`,
+    ///   for more information.
+    /// * `limits` - Limits which override those set with
+    ///   [set_match_limit()](#method.set_match_limit) and
+    ///   [set_match_limit_recursion()](#method.set_match_limit_recursion).
+    #[inline]
+    pub fn exec_from_with_limits<'a>(&self, subject: &'a str, startoffset: uint, options: &EnumSet<ExecOption>, limits: &MatchLimits) -> Result<Option<Match<'a>>, ExecError> {
+        full_match(self.exec_once(subject, startoffset, options, limits, ptr::mut_null()))
     }
 
-    /// Creates a `MatchIterator` for iterating through matches within the given subject
-    /// string `subject`.
+    /// Matches the compiled regular expression against a given subject string `subject`
+    /// like [exec_from_with_options()](#method.exec_from_with_options), calling `callout`
+    /// at each callout point reached during this call.
+    ///
+    /// The callout function decides whether matching continues by returning
+    /// `CalloutContinue`, `CalloutFail` or `CalloutAbort`. It must not fail, because the
+    /// task cannot unwind through libpcre.
+    ///
+    /// # Arguments
+    /// * `subject` - The subject string.
+    /// * `startoffset` - Starting offset within `subject` at which to begin looking for
+    ///   a match.
+    /// * `options` - Bitwise-OR'd matching options. See the libpcre manpages, `man 3 pcre_exec`,
+    ///   for more information.
+    /// * `callout` - The callout function.
+    pub fn exec_with_callout<'a>(&self, subject: &'a str, startoffset: uint, options: &EnumSet<ExecOption>, callout: |&CalloutBlock| -> CalloutResult) -> Result<Option<Match<'a>>, ExecError> {
+        let mut callout = callout;
+        let callout_data = &mut callout as *mut |&CalloutBlock| -> CalloutResult as *mut c_void;
+        full_match(self.exec_once(subject, startoffset, options, &MatchLimits::new(), callout_data))
+    }
+
+    /// Matches the compiled regular expression against a given subject string `subject`,
+    /// reporting a partial match if the end of the subject is reached before the regular
+    /// expression could match. See [exec_partial_from_with_options()](#method.exec_partial_from_with_options).
     ///
     /// # Argument
     /// * `subject` - The subject string.
     #[inline]
-    pub fn matches<'a>(&self, subject: &'a str) -> MatchIterator<'a> {
+    pub fn exec_partial<'a>(&self, subject: &'a str) -> Result<PartialMatchResult<'a>, ExecError> {
         let no_options: EnumSet<ExecOption> = EnumSet::empty();
-        self.matches_with_options(subject, &no_options)
+        self.exec_partial_from_with_options(subject, 0u, &no_options)
     }
 
-    /// Creates a `MatchIterator` for iterating through matches within the given subject
-    /// string `subject` using the given bitwise-OR'd matching options `options`.
+    /// Matches the compiled regular expression against a given subject string `subject`
+    /// starting at offset `startoffset` and using the given bitwise-OR'd matching options
+    /// `options`, reporting partial matches.
+    ///
+    /// If `options` contains neither `ExecPartialSoft` nor `ExecPartialHard`, then
+    /// `ExecPartialSoft` is used: a complete match is preferred over a partial one. With
+    /// `ExecPartialHard`, a partial match is reported as soon as one is found, which is
+    /// what is wanted when matching a subject that arrives in chunks. In that case, the
+    /// next search should include the subject from the `start` of the partial match. See
+    /// the libpcre manpages, `man 3 pcrepartial`, for more information.
+    ///
+    /// For example, a partial match of `^\d{4}-\d{2}$` against `"2014-0"` means that
+    /// the input could still become valid as the user types.
     ///
     /// # Arguments
     /// * `subject` - The subject string.
+    /// * `startoffset` - Starting offset within `subject` at which to begin looking for
+    ///   a match.
     /// * `options` - Bitwise-OR'd matching options. See the libpcre manpages, `man 3 pcre_exec`,
     ///   for more information.
-    #[inline]
-    pub fn matches_with_options<'a>(&self, subject: &'a str, options: &EnumSet<ExecOption>) -> MatchIterator<'a> {
-        unsafe {
-            let ovecsize = (self.capture_count_ + 1) * 3;
-            MatchIterator {
-                code: { detail::pcre_refcount(self.code as *mut detail::pcre, 1); self.code },
-                extra: self.extra as *detail::pcre_extra,
-                capture_count: self.capture_count_,
-                subject: subject,
-                subject_cstring: subject.to_c_str_unchecked(), // the subject string can contain NUL bytes
-                offset: 0,
-                options: options.clone(),
-                ovector: vec::from_elem(ovecsize as uint, 0 as c_int)
-            }
+    pub fn exec_partial_from_with_options<'a>(&self, subject: &'a str, startoffset: uint, options: &EnumSet<ExecOption>) -> Result<PartialMatchResult<'a>, ExecError> {
+        let mut partial_options = options.clone();
+        if !options.contains_elem(ExecPartialSoft) && !options.contains_elem(ExecPartialHard) {
+            partial_options.add(ExecPartialSoft);
         }
+        self.exec_once(subject, startoffset, &partial_options, &MatchLimits::new(), ptr::mut_null())
     }
 
-    /// Returns the number of named capture groups in the regular expression.
-    pub fn name_count(&self) -> uint {
-        unsafe {
-            let mut name_count: c_int = 0;
-            detail::pcre_fullinfo(self.code, self.extra as *detail::pcre_extra, detail::PCRE_INFO_NAMECOUNT, &mut name_count as *mut c_int as *mut c_void);
-            name_count as uint
+    /// Creates a `MatchData` for match attempts of this regular expression with
+    /// [exec_with_match_data()](#method.exec_with_match_data).
+    pub fn match_data(&self) -> MatchData {
+        MatchData {
+            ovector: vec::from_elem(((self.capture_count_ + 1) * 3) as uint, 0 as c_int),
+            capture_count: self.capture_count_,
+            string_count_: 0,
+            mark: None
         }
     }
 
-    /// Creates a name-to-number translation table that maps the name of each named capture
-    /// group to the assigned group numbers.
+    /// Matches the compiled regular expression against a given subject string `subject`
+    /// starting at offset `startoffset`, leaving the capture group offsets of a match in
+    /// `data` instead of returning a `Match`. Returns whether the regular expression
+    /// matched.
     ///
-    /// The value type of the returned `TreeMap` is a `uint` vector because there can be
-    /// more than one group number for a given name if the PCRE_DUPNAMES option is used
-    /// when compiling the regular expression.
-    pub fn name_table(&self) -> TreeMap<~str, ~[uint]> {
-        unsafe {
-            let name_count = self.name_count();
-            let mut tabptr: *c_uchar = ptr::null();
-            detail::pcre_fullinfo(self.code, self.extra as *detail::pcre_extra, detail::PCRE_INFO_NAMETABLE, &mut tabptr as *mut *c_uchar as *mut c_void);
-            let mut name_entry_size: c_int = 0;
-            detail::pcre_fullinfo(self.code, self.extra as *detail::pcre_extra, detail::PCRE_INFO_NAMEENTRYSIZE, &mut name_entry_size as *mut c_int as *mut c_void);
-
-            let mut name_table: TreeMap<~str, ~[uint]> = TreeMap::new();
-
-            let mut i = 0u;
-            while i < name_count {
-                let n: uint = (ptr::read(tabptr) as uint << 8) | (ptr::read(tabptr.offset(1)) as uint);
-                let name_cstring = c_str::CString::new(tabptr.offset(2) as *c_char, false);
-                let name: ~str = name_cstring.as_str().unwrap().to_owned();
-                // TODO Avoid the double lookup.
-                // https://github.com/mozilla/rust/issues/9068
-                if !name_table.contains_key(&name) {
-                    name_table.insert(name, ~[n]);
-                } else {
-                    name_table.find_mut(&name).unwrap().push(n);
-                }
-                tabptr = tabptr.offset(name_entry_size as int);
-                i += 1;
-            }
-
-            name_table
+    /// No memory is allocated unless `data` was created for a regular expression with fewer
+    /// capture groups, or a `(*MARK)` name is passed.
+    ///
+    /// # Arguments
+    /// * `subject` - The subject string.
+    /// * `startoffset` - Starting offset within `subject` at which to begin looking for
+    ///   a match.
+    /// * `options` - Bitwise-OR'd matching options. See the libpcre manpages, `man 3 pcre_exec`,
+    ///   for more information.
+    /// * `data` - The scratch space, which receives the capture group offsets.
+    ///
+    /// # Example
+    /// ```notrust
+    /// let re = Pcre::compile("(\\w+)=(\\d+)").unwrap();
+    /// let mut data = re.match_data();
+    /// let no_options = EnumSet::empty();
+    /// for line in lines.iter() {
+    ///     if re.exec_into(*line, 0, &no_options, &mut data).unwrap() {
+    ///         let value = data.get(*line, 2).unwrap();
+    ///     }
+    /// }
+    /// ```
+    pub fn exec_into(&self, subject: &str, startoffset: uint, options: &EnumSet<ExecOption>, data: &mut MatchData) -> Result<bool, ExecError> {
+        let rc = try!(self.exec_in_place(subject, startoffset, options, &MatchLimits::new(), ptr::mut_null(), data));
+        if rc >= 0 {
+            Ok(true)
+        } else if rc == detail::PCRE_ERROR_PARTIAL {
+            Err(PartialMatchError)
+        } else {
+            Ok(false)
         }
     }
 
-    /// Studies the regular expression to see if additional information can be extracted
-    /// which might speed up matching.
+    /// Returns whether the compiled regular expression matches anywhere in the given subject
+    /// string `subject`.
     ///
-    /// # Return value
-    /// `true` if additional information could be extracted. `false` otherwise.
-    pub fn study(&mut self) -> bool {
-        let no_options: EnumSet<StudyOption> = EnumSet::empty();
-        self.study_with_options(&no_options)
-    }
-
-    /// Studies the regular expression using the given bitwise-OR'd study options `options`
-    /// to see if additional information can be extracted which might speed up matching.
+    /// This is faster than [exec()](#method.exec) because libpcre is not asked for the
+    /// offsets of the match, and no `Match` is created.
     ///
     /// # Argument
-    /// * `options` - Study options. See the libpcre manpages, `man 3 pcre_study`, for more
-    ///   information about each option.
-    ///
-    /// # Return value
-    /// `true` if additional information could be extracted. `false` otherwise.
-    pub fn study_with_options(&mut self, options: &EnumSet<StudyOption>) -> bool {
+    /// * `subject` - The subject string.
+    pub fn is_match(&self, subject: &str) -> Result<bool, ExecError> {
+        try!(self.check_str_subject(subject, 0u));
+        let no_options: EnumSet<ExecOption> = EnumSet::empty();
+        let mut ovector: [c_int, ..0] = [];
         unsafe {
-            // If something else has a reference to `code` then it probably has a pointer to
-            // the current study data (if any). Thus, we shouldn't free the current study data
-            // in that case.
-            if detail::pcre_refcount(self.code as *mut detail::pcre, 0) != 1 {
-                false
+            let subject_c_str = subject.as_ptr() as *c_char;
+            let mut mark: *mut c_uchar = ptr::mut_null();
+            // With an ovector size of 0, a match returns 0.
+            let rc = self.exec_raw(subject_c_str, subject.len(), 0u, &no_options, &MatchLimits::new(), ptr::mut_null(), false, &mut mark, ovector.as_mut_slice());
+            if rc >= 0 {
+                Ok(true)
+            } else if rc == detail::PCRE_ERROR_NOMATCH {
+                Ok(false)
             } else {
-                // Free any current study data.
-                detail::pcre_free_study(self.extra as *mut detail::pcre_extra);
-                self.extra = ptr::mut_null();
-
-                let extra = detail::pcre_study(self.code, options);
-                self.extra = extra;
-                extra.is_not_null()
+                Err(exec_error(rc, ovector.as_slice()))
             }
         }
     }
 
-    /// Returns the mark from pcre if it was set in the extra options
-    /// TODO: I have changed it so the Match returns a mark on it instead 
-    /// since it makes much more sense to have it there. Update the tests to use that.
-    /// 
-    /// # Return value
-    /// `Some(str)` if pcre returned a value for the mark
-    /// `None` if either there was no mark in the match or the Extra option was not set to begin with 
-    pub fn get_mark(&self) -> Option<~str> {
-        unsafe {
-            if self.mark.is_not_null() {
-                return Some(std::str::raw::from_c_str(self.mark as *i8));
-            }
-            None
+    /// Returns the number of matches within the given subject string `subject`, counting
+    /// the matches found by a `MatchIterator` without creating them.
+    ///
+    /// # Argument
+    /// * `subject` - The subject string.
+    pub fn count_matches(&self, subject: &str) -> Result<uint, ExecError> {
+        let mut it = self.matches(subject);
+        let mut mark: *mut c_uchar = ptr::mut_null();
+        let mut count = 0u;
+        while try!(it.next_match(ptr::mut_null(), &mut mark)).is_some() {
+            count += 1;
         }
+        Ok(count)
     }
 
-    /// Sets the extra options on this pcre. Note that only mark is fully implemented right now.
+    /// Matches the compiled regular expression against a given subject string `subject`
+    /// like [exec_partial_from_with_options()](#method.exec_partial_from_with_options),
+    /// using `data` as scratch space and storing the mark of the match attempt in it.
     ///
-    /// # Argument
-    /// * `options` - Extra Options. See `man pcreapi`  for more info about each option
-    ///   (search for "Extra data for pcre_exec")
+    /// Partial matches are only reported if `options` contains `ExecPartialSoft` or
+    /// `ExecPartialHard`.
     ///
-    /// # Return value
-    /// `false` if this pcre has not been studied yet. Call a study() function before calling this one
-    /// `true` if the function was successful
-    pub fn set_extra_options(&mut self, options: &EnumSet<ExtraOption>) -> bool {
-        unsafe {
-            if self.extra.is_null() {
-                return false;
+    /// # Arguments
+    /// * `subject` - The subject string.
+    /// * `startoffset` - Starting offset within `subject` at which to begin looking for
+    ///   a match.
+    /// * `options` - Bitwise-OR'd matching options. See the libpcre manpages, `man 3 pcre_exec`,
+    ///   for more information.
+    /// * `data` - The scratch space, which also reports the mark of failed and partial
+    ///   matches.
+    pub fn exec_with_match_data<'a>(&self, subject: &'a str, startoffset: uint, options: &EnumSet<ExecOption>, data: &mut MatchData) -> Result<PartialMatchResult<'a>, ExecError> {
+        self.exec_internal(subject, startoffset, options, &MatchLimits::new(), ptr::mut_null(), data)
+    }
+
+    /// Creates a `DfaMatcher` for matching this regular expression with the DFA algorithm.
+    #[inline]
+    pub fn dfa_matcher<'p>(&'p self) -> DfaMatcher<'p> {
+        DfaMatcher {
+            re: self,
+            workspace: vec::from_elem(DEFAULT_DFA_WORKSPACE_SIZE, 0 as c_int),
+            shortest: false,
+            max_matches: DEFAULT_DFA_MAX_MATCHES
+        }
+    }
+
+    fn exec_internal<'a>(&self, subject: &'a str, startoffset: uint, options: &EnumSet<ExecOption>, limits: &MatchLimits, callout_data: *mut c_void, data: &mut MatchData) -> Result<PartialMatchResult<'a>, ExecError> {
+        let rc = try!(self.exec_in_place(subject, startoffset, options, limits, callout_data, data));
+        if rc >= 0 {
+            Ok(Full(Match {
+                subject: subject,
+                partial_ovector: data.ovector.slice_to(((self.capture_count_ + 1) * 2) as uint).to_owned(),
+                string_count_: rc,
+                name_table: self.name_table_.clone(),
+                mark: data.mark.clone()
+            }))
+        } else {
+            Ok(unmatched_result(rc, data.ovector.as_slice()))
+        }
+    }
+
+    /// Runs a match attempt like `exec_internal()` with a `MatchData` of its own. The
+    /// `MatchData` is not used again, so the `Match` takes over its offset vector and mark
+    /// instead of copying them, and a match attempt makes a single allocation.
+    fn exec_once<'a>(&self, subject: &'a str, startoffset: uint, options: &EnumSet<ExecOption>, limits: &MatchLimits, callout_data: *mut c_void) -> Result<PartialMatchResult<'a>, ExecError> {
+        let mut data = self.match_data();
+        let rc = try!(self.exec_in_place(subject, startoffset, options, limits, callout_data, &mut data));
+        if rc >= 0 {
+            let MatchData { ovector: mut partial_ovector, mark: mark, .. } = data;
+            partial_ovector.truncate(((self.capture_count_ + 1) * 2) as uint);
+            Ok(Full(Match {
+                subject: subject,
+                partial_ovector: partial_ovector,
+                string_count_: rc,
+                name_table: self.name_table_.clone(),
+                mark: mark
+            }))
+        } else {
+            Ok(unmatched_result(rc, data.ovector.as_slice()))
+        }
+    }
+
+    /// Runs a match attempt against `subject`, filling in `data`. Returns the return code
+    /// of libpcre if it is a match, `PCRE_ERROR_NOMATCH` or `PCRE_ERROR_PARTIAL`, and an
+    /// `ExecError` otherwise.
+    fn exec_in_place(&self, subject: &str, startoffset: uint, options: &EnumSet<ExecOption>, limits: &MatchLimits, callout_data: *mut c_void, data: &mut MatchData) -> Result<c_int, ExecError> {
+        let ovecsize = ((self.capture_count_ + 1) * 3) as uint;
+        if data.ovector.len() < ovecsize {
+            // The MatchData was created for a regular expression with fewer groups.
+            data.ovector = vec::from_elem(ovecsize, 0 as c_int);
+        }
+        data.capture_count = self.capture_count_;
+        data.string_count_ = 0;
+        try!(self.check_str_subject(subject, startoffset));
+
+        unsafe {
+            let subject_c_str = subject.as_ptr() as *c_char;
+            // A `&str` is always valid UTF-8, so libpcre's check can be skipped.
+            let mut mark: *mut c_uchar = ptr::mut_null();
+            let rc = self.exec_raw(subject_c_str, subject.len(), startoffset, options, limits, callout_data, false, &mut mark, data.ovector.mut_slice_to(ovecsize));
+            data.string_count_ = if rc >= 0 { rc } else { 0 };
+            data.mark = mark_name(mark);
+            if rc >= 0 || rc == detail::PCRE_ERROR_NOMATCH || rc == detail::PCRE_ERROR_PARTIAL {
+                Ok(rc)
+            } else {
+                Err(exec_error(rc, data.ovector.as_slice()))
+            }
+        }
+    }
+
+    /// Returns `NonUtf8PatternError` if this regular expression was not compiled in UTF-8
+    /// mode, and `BadUtf8OffsetError` if `startoffset` is inside a character of `subject`.
+    /// libpcre is told not to check `&str` subjects, and the behaviour of a match attempt
+    /// from such an offset is undefined.
+    fn check_str_subject(&self, subject: &str, startoffset: uint) -> Result<(), ExecError> {
+        if !self.utf8 {
+            Err(NonUtf8PatternError)
+        } else if startoffset <= subject.len() && !subject.is_char_boundary(startoffset) {
+            Err(BadUtf8OffsetError)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Matches the compiled regular expression against the byte string `subject`.
+    /// See [exec_bytes_from_with_options()](#method.exec_bytes_from_with_options).
+    ///
+    /// # Argument
+    /// * `subject` - The subject byte string.
+    #[inline]
+    pub fn exec_bytes<'a>(&self, subject: &'a [u8]) -> Result<Option<BytesMatch<'a>>, ExecError> {
+        self.exec_bytes_from(subject, 0)
+    }
+
+    /// Matches the compiled regular expression against the byte string `subject` starting
+    /// at offset `startoffset`.
+    /// See [exec_bytes_from_with_options()](#method.exec_bytes_from_with_options).
+    ///
+    /// # Arguments
+    /// * `subject` - The subject byte string.
+    /// * `startoffset` - Starting offset within `subject` at which to begin looking for
+    ///   a match.
+    #[inline]
+    pub fn exec_bytes_from<'a>(&self, subject: &'a [u8], startoffset: uint) -> Result<Option<BytesMatch<'a>>, ExecError> {
+        let no_options: EnumSet<ExecOption> = EnumSet::empty();
+        self.exec_bytes_from_with_options(subject, startoffset, &no_options)
+    }
+
+    /// Matches the compiled regular expression against the byte string `subject` starting
+    /// at offset `startoffset` and using the given bitwise-OR'd matching options `options`.
+    /// If no match is found, then `Ok(None)` is returned. Otherwise, a `BytesMatch` object
+    /// is returned which provides access to the captured substrings as slices of the
+    /// subject byte string.
+    ///
+    /// If the regular expression was compiled in UTF-8 mode (that is, not with
+    /// [compile_bytes()](#method.compile_bytes)), then libpcre first checks that `subject`
+    /// is valid UTF-8 and returns a `BadUtf8Error` locating the first invalid character if
+    /// it is not. Other failures are also reported as an `ExecError`.
+    ///
+    /// # Arguments
+    /// * `subject` - The subject byte string.
+    /// * `startoffset` - Starting offset within `subject` at which to begin looking for
+    ///   a match.
+    /// * `options` - Bitwise-OR'd matching options. See the libpcre manpages, `man 3 pcre_exec`,
+    ///   for more information.
+    pub fn exec_bytes_from_with_options<'a>(&self, subject: &'a [u8], startoffset: uint, options: &EnumSet<ExecOption>) -> Result<Option<BytesMatch<'a>>, ExecError> {
+        let ovecsize = (self.capture_count_ + 1) * 3;
+        let mut ovector: ~[c_int] = vec::from_elem(ovecsize as uint, 0 as c_int);
+
+        unsafe {
+            let subject_c_str = subject.as_ptr() as *c_char;
+            let check_utf8 = self.utf8;
+            let mut mark: *mut c_uchar = ptr::mut_null();
+            let rc = self.exec_raw(subject_c_str, subject.len(), startoffset, options, &MatchLimits::new(), ptr::mut_null(), check_utf8, &mut mark, ovector.as_mut_slice());
+            if rc >= 0 {
+                Ok(Some(BytesMatch {
+                    subject: subject,
+                    partial_ovector: ovector.slice_to(((self.capture_count_ + 1) * 2) as uint).to_owned(),
+                    string_count_: rc,
+                    name_table: self.name_table_.clone()
+                }))
+            } else if rc == detail::PCRE_ERROR_NOMATCH {
+                Ok(None)
+            } else {
+                Err(exec_error(rc, ovector.as_slice()))
+            }
+        }
+    }
+
+    /// Runs `pcre_exec()` against the `length` bytes at `subject`, storing the name of the
+    /// last `(*MARK)` passed in `mark`. libpcre is given the length of the subject, so it
+    /// does not need to be NUL-terminated and is not copied.
+    ///
+    /// All of the state of the match attempt is passed in, so that a `Pcre` can be shared.
+    unsafe fn exec_raw(&self, subject: *c_char, length: uint, startoffset: uint, options: &EnumSet<ExecOption>, limits: &MatchLimits, callout_data: *mut c_void, check_utf8: bool, mark: &mut *mut c_uchar, ovector: &mut [c_int]) -> c_int {
+        let mut extra = limits.or(&self.limits).apply(self.extra.ptr as *detail::pcre_extra);
+        extra.flags |= self.extra_options;
+        apply_match_tables(&mut extra, &self.match_tables);
+        // The copy lives on the stack, so that a match attempt does not allocate. The mark
+        // location is set in the copy, since the study data is shared with the clones of
+        // this Pcre. Marks are always requested, whether or not the regular
+        // expression has been studied.
+        set_mark_location(&mut extra, mark);
+        set_callout_data(&mut extra, callout_data);
+        exec_jit_or_interpreted(self.code.ptr, &extra, self.jit, self.jit_stack.stack(), subject, length as c_int, startoffset as c_int, options, check_utf8, ovector)
+    }
+
+    /// Creates a `MatchIterator` for iterating through matches within the given subject
+    /// string `subject`.
+    ///
+    /// # Argument
+    /// * `subject` - The subject string.
+    #[inline]
+    pub fn matches<'a>(&self, subject: &'a str) -> MatchIterator<'a> {
+        let no_options: EnumSet<ExecOption> = EnumSet::empty();
+        self.matches_with_options(subject, &no_options)
+    }
+
+    /// Creates a `MatchIterator` for iterating through matches within the given subject
+    /// string `subject` using the given bitwise-OR'd matching options `options`.
+    ///
+    /// # Arguments
+    /// * `subject` - The subject string.
+    /// * `options` - Bitwise-OR'd matching options. See the libpcre manpages, `man 3 pcre_exec`,
+    ///   for more information.
+    #[inline]
+    pub fn matches_with_options<'a>(&self, subject: &'a str, options: &EnumSet<ExecOption>) -> MatchIterator<'a> {
+        self.matches_with_limits(subject, options, &MatchLimits::new())
+    }
+
+    /// Creates a `MatchIterator` for iterating through matches within the given subject
+    /// string `subject` using the given bitwise-OR'd matching options `options` and
+    /// overriding the match limits of the regular expression for every match attempt.
+    /// See [exec_from_with_limits()](#method.exec_from_with_limits).
+    ///
+    /// # Arguments
+    /// * `subject` - The subject string.
+    /// * `options` - Bitwise-OR'd matching options. See the libpcre manpages, `man 3 pcre_exec`,
+    ///   for more information.
+    /// * `limits` - Limits which override those of the regular expression.
+    pub fn matches_with_limits<'a>(&self, subject: &'a str, options: &EnumSet<ExecOption>, limits: &MatchLimits) -> MatchIterator<'a> {
+        let ovecsize = (self.capture_count_ + 1) * 3;
+        MatchIterator {
+            code: self.code.clone(),
+            extra: self.extra.clone(),
+            capture_count: self.capture_count_,
+            name_table: self.name_table_.clone(),
+            subject: subject,
+            offset: 0,
+            options: options.clone(),
+            limits: limits.or(&self.limits),
+            match_tables: self.match_tables.clone(),
+            jit: self.jit,
+            jit_stack: self.jit_stack.clone(),
+            ovector: vec::from_elem(ovecsize as uint, 0 as c_int),
+            last_match_empty: false,
+            crlf_is_newline: crlf_is_newline(self.code.ptr, options),
+            error: if self.utf8 { None } else { Some(NonUtf8PatternError) }
+        }
+    }
+
+    /// Replaces the first match of the regular expression within `subject` with the
+    /// replacement text given by `rep`.
+    ///
+    /// `rep` is either a template string or a closure taking a `&Match`. In a template,
+    /// `$n` and `${n}` are replaced by capture group `n`, `${name}` by the named capture
+    /// group `name`, and `$$` by a literal `$`. Groups that did not participate in the
+    /// match are replaced by the empty string.
+    ///
+    /// # Arguments
+    /// * `subject` - The subject string.
+    /// * `rep` - The replacement template or closure.
+    ///
+    /// # Return value
+    /// The subject string with the replacement applied. If there is no match, then `subject`
+    /// itself is returned as a slice and nothing is allocated. If a match attempt fails,
+    /// then the `ExecError` is returned.
+    #[inline]
+    pub fn replace<'a, R: Replacer>(&self, subject: &'a str, rep: R) -> Result<MaybeOwned<'a>, ExecError> {
+        self.replace_n(subject, 1u, rep)
+    }
+
+    /// Replaces every match of the regular expression within `subject` with the replacement
+    /// text given by `rep`.
+    ///
+    /// See [replace()](#method.replace) for the template syntax.
+    ///
+    /// # Arguments
+    /// * `subject` - The subject string.
+    /// * `rep` - The replacement template or closure.
+    #[inline]
+    pub fn replace_all<'a, R: Replacer>(&self, subject: &'a str, rep: R) -> Result<MaybeOwned<'a>, ExecError> {
+        self.replace_n(subject, 0u, rep)
+    }
+
+    /// Replaces at most `limit` matches of the regular expression within `subject` with the
+    /// replacement text given by `rep`. A `limit` of zero replaces every match.
+    ///
+    /// See [replace()](#method.replace) for the template syntax.
+    ///
+    /// # Arguments
+    /// * `subject` - The subject string.
+    /// * `limit` - The maximum number of matches to replace, or zero for no limit.
+    /// * `rep` - The replacement template or closure.
+    pub fn replace_n<'a, R: Replacer>(&self, subject: &'a str, limit: uint, mut rep: R) -> Result<MaybeOwned<'a>, ExecError> {
+        let mut it = self.matches(subject);
+        let mut opt_m = match it.try_next() {
+            Err(err) => return Err(err),
+            Ok(None) => return Ok(Slice(subject)),
+            Ok(opt_m) => opt_m
+        };
+
+        let mut result = str::with_capacity(subject.len());
+        let mut last_end = 0u;
+        let mut count = 0u;
+        loop {
+            let m = match opt_m {
+                None => break,
+                Some(m) => m
+            };
+            result.push_str(subject.slice(last_end, m.group_start(0u)));
+            result.push_str(rep.reg_replace(&m).as_slice());
+            last_end = m.group_end(0u);
+
+            count += 1;
+            if limit != 0 && count >= limit {
+                break;
+            }
+            opt_m = match it.try_next() {
+                Err(err) => return Err(err),
+                Ok(opt_m) => opt_m
+            };
+        }
+        result.push_str(subject.slice_from(last_end));
+
+        Ok(Owned(result))
+    }
+
+    /// Splits `subject` around the matches of the regular expression, following Perl's
+    /// rules for `split`:
+    ///
+    /// * If the regular expression contains capture groups, then the substrings captured
+    ///   by each separator are returned between the fields, with groups that did not
+    ///   participate in the match returned as empty strings.
+    /// * An empty match at the start or at the end of `subject` does not produce an empty
+    ///   field.
+    /// * Trailing empty fields are dropped.
+    /// * Splitting the empty string produces no fields.
+    ///
+    /// If a match attempt fails, then the `ExecError` is returned.
+    ///
+    /// # Argument
+    /// * `subject` - The subject string.
+    ///
+    /// # See also
+    /// * [splitn()](#method.splitn) - Splits into at most a given number of fields.
+    /// * [split_iter()](#method.split_iter) - Splits lazily.
+    #[inline]
+    pub fn split<'a>(&self, subject: &'a str) -> Result<~[&'a str], ExecError> {
+        self.splitn(subject, 0u)
+    }
+
+    /// Splits `subject` into at most `limit` fields around the matches of the regular
+    /// expression. The last field contains the remainder of the subject string.
+    ///
+    /// As in Perl, trailing empty fields are kept when a limit is given. A `limit` of zero
+    /// means no limit, in which case this is the same as [split()](#method.split).
+    ///
+    /// # Arguments
+    /// * `subject` - The subject string.
+    /// * `limit` - The maximum number of fields, not counting captured separators.
+    #[inline]
+    pub fn splitn<'a>(&self, subject: &'a str, limit: uint) -> Result<~[&'a str], ExecError> {
+        let mut it = self.splitn_iter(subject, limit);
+        let fields: ~[&'a str] = it.by_ref().collect();
+        match it.error() {
+            None => Ok(fields),
+            Some(err) => Err(err)
+        }
+    }
+
+    /// Creates a `SplitIterator` that lazily splits `subject` around the matches of the
+    /// regular expression. See [split()](#method.split) for the splitting rules.
+    ///
+    /// # Argument
+    /// * `subject` - The subject string.
+    #[inline]
+    pub fn split_iter<'a>(&self, subject: &'a str) -> SplitIterator<'a> {
+        self.splitn_iter(subject, 0u)
+    }
+
+    /// Creates a `SplitIterator` that lazily splits `subject` into at most `limit` fields.
+    /// See [splitn()](#method.splitn) for the splitting rules.
+    ///
+    /// # Arguments
+    /// * `subject` - The subject string.
+    /// * `limit` - The maximum number of fields, not counting captured separators, or zero
+    ///   for no limit.
+    pub fn splitn_iter<'a>(&self, subject: &'a str, limit: uint) -> SplitIterator<'a> {
+        SplitIterator {
+            matches: self.matches(subject),
+            subject: subject,
+            limit: limit,
+            field_count: 0u,
+            last_end: 0u,
+            queue: ~[],
+            pending_empty: 0u,
+            finished: subject.is_empty()
+        }
+    }
+
+    /// Returns the number of named capture groups in the regular expression.
+    pub fn name_count(&self) -> uint {
+        self.name_table_.iter().fold(0u, |count, &(_, ref n_vec)| count + n_vec.len())
+    }
+
+    /// Returns information about the compiled regular expression and its study data.
+    pub fn info(&self) -> Result<PatternInfo, InfoError> {
+        let code = self.code.ptr;
+        let extra = self.extra.ptr as *detail::pcre_extra;
+        unsafe {
+            pattern_info(code, extra).map_err(|rc| InfoError { code: rc })
+        }
+    }
+
+    /// Saves the compiled regular expression and its study data, so that it can be loaded
+    /// again with [load()](#method.load) without being recompiled.
+    ///
+    /// The saved bytes also hold the pattern, its compile and study options and the character
+    /// tables it was compiled with, so that it can be recompiled and studied again. JIT-compiled
+    /// code is not saved, nor are match limits, extra options and tables set with
+    /// [set_tables()](#method.set_tables).
+    pub fn save(&self) -> ~[u8] {
+        let code = self.code.ptr;
+        let extra = self.extra.ptr as *detail::pcre_extra;
+        let mut bytes: ~[u8] = ~[];
+        bytes.push_all(SAVE_MAGIC);
+        push_u32(&mut bytes, SAVE_FORMAT_VERSION);
+        push_block(&mut bytes, pcre_version().as_bytes());
+        push_u32(&mut bytes, mem::size_of::<uint>() as u32);
+        push_u32(&mut bytes, build_config().link_size as u32);
+        push_u32(&mut bytes, if self.utf8 { 1 } else { 0 });
+        push_u32(&mut bytes, compile_option_bits(&self.code.options) as u32);
+        push_block(&mut bytes, self.code.pattern);
+        unsafe {
+            match self.code.tables {
+                None => push_block(&mut bytes, []),
+                Some(ref tables) => vec::raw::buf_as_slice(tables.tables, detail::PCRE_TABLES_LENGTH, |tables| {
+                    push_block(&mut bytes, tables)
+                })
+            }
+
+            let size: size_t = fullinfo(code, extra, detail::PCRE_INFO_SIZE, 0).unwrap();
+            vec::raw::buf_as_slice(code as *u8, size as uint, |code| push_block(&mut bytes, code));
+
+            // Only the study data proper is saved. The rest of the extra block is filled in
+            // for each match attempt.
+            if extra.is_not_null() && ((*extra).flags & detail::PCRE_EXTRA_STUDY_DATA) != 0 {
+                let study_size: size_t = fullinfo(code, extra, detail::PCRE_INFO_STUDYSIZE, 0).unwrap();
+                vec::raw::buf_as_slice((*extra).study_data as *u8, study_size as uint, |study_data| {
+                    push_block(&mut bytes, study_data)
+                });
+            } else {
+                push_block(&mut bytes, []);
+            }
+        }
+        match self.extra.options {
+            None => {
+                push_u32(&mut bytes, 0);
+                push_u32(&mut bytes, 0);
+            },
+            Some(ref study_options) => {
+                push_u32(&mut bytes, 1);
+                push_u32(&mut bytes, study_options.iter().fold(0u32, |bits, option| bits | option as u32));
+            }
+        }
+        let checksum = save_checksum(bytes);
+        push_u32(&mut bytes, checksum);
+        bytes
+    }
+
+    /// Loads a regular expression saved by [save()](#method.save), recompiling the pattern
+    /// and studying it again with the saved options.
+    ///
+    /// # Argument
+    /// * `bytes` - The bytes returned by `save()`.
+    ///
+    /// # Return value
+    /// The regular expression, or `MalformedSaveError` if the bytes were not returned by
+    /// `save()`, `RecompileError` if the pattern could not be recompiled, or `RestudyError`
+    /// if it could not be studied again.
+    pub fn load(bytes: &[u8]) -> Result<Pcre, LoadError> {
+        try!(read_saved(bytes)).recompile()
+    }
+
+    /// Loads a regular expression saved by [save()](#method.save) like [load()](#method.load),
+    /// but without recompiling it if the bytes were saved by the same version and build of
+    /// libpcre. The compiled code and study data are then used as they are, converted to
+    /// the byte order of this machine if necessary. Otherwise, or if libpcre rejects the
+    /// compiled code, the pattern is recompiled and studied again.
+    ///
+    /// # Safety
+    /// libpcre does not validate compiled code, and the checksum only detects accidental
+    /// corruption, so crafted bytes can make matching read or write out of bounds. Only
+    /// load bytes which were saved by a trusted source.
+    ///
+    /// # Argument
+    /// * `bytes` - The bytes returned by `save()`.
+    pub unsafe fn load_unchecked(bytes: &[u8]) -> Result<Pcre, LoadError> {
+        let saved = try!(read_saved(bytes));
+        // The layout of the compiled code depends on the libpcre version, the link size and
+        // the size of a pointer. Only the byte order is converted by libpcre.
+        if saved.version == pcre_version().as_bytes() && saved.pointer_size as uint == mem::size_of::<uint>()
+            && saved.link_size as uint == build_config().link_size {
+            match load_code(&saved) {
+                Some(re) => return Ok(re),
+                None => ()
+            }
+        }
+        saved.recompile()
+    }
+
+    /// Returns the name-to-number translation table that maps the name of each named
+    /// capture group to the assigned group numbers.
+    ///
+    /// The value type of the returned `TreeMap` is a `uint` vector because there can be
+    /// more than one group number for a given name if the PCRE_DUPNAMES option is used
+    /// when compiling the regular expression.
+    ///
+    /// Named groups can also be accessed through
+    /// [Match::group_by_name()](struct.Match.html#method.group_by_name), which does not
+    /// need this table.
+    pub fn name_table(&self) -> TreeMap<~str, ~[uint]> {
+        self.name_table_.iter().map(|&(ref name, ref n_vec)| (name.clone(), n_vec.clone())).collect()
+    }
+
+    /// Returns the match limit set with [set_match_limit()](#method.set_match_limit), or
+    /// `None` if libpcre's default applies.
+    pub fn match_limit(&self) -> Option<uint> {
+        self.limits.match_limit
+    }
+
+    /// Sets the maximum number of times libpcre's internal `match()` function may be called
+    /// during a single match attempt. If the limit is reached, then `MatchLimitError` is
+    /// returned. See the libpcre manpages, `man 3 pcreapi`, for more information.
+    ///
+    /// The limit applies to every later match attempt, including those of `MatchIterator`s
+    /// created afterwards, whether or not the regular expression has been studied.
+    ///
+    /// # Argument
+    /// * `limit` - The match limit.
+    pub fn set_match_limit(&mut self, limit: uint) {
+        self.limits.match_limit = Some(limit);
+    }
+
+    /// Returns the recursion limit set with
+    /// [set_match_limit_recursion()](#method.set_match_limit_recursion), or `None` if
+    /// libpcre's default applies.
+    pub fn match_limit_recursion(&self) -> Option<uint> {
+        self.limits.match_limit_recursion
+    }
+
+    /// Sets the maximum depth of recursion of libpcre's internal `match()` function during
+    /// a single match attempt. If the limit is reached, then `RecursionLimitError` is
+    /// returned. See the libpcre manpages, `man 3 pcreapi`, for more information.
+    ///
+    /// The limit applies to every later match attempt, including those of `MatchIterator`s
+    /// created afterwards, whether or not the regular expression has been studied.
+    ///
+    /// # Argument
+    /// * `limit` - The recursion limit.
+    pub fn set_match_limit_recursion(&mut self, limit: uint) {
+        self.limits.match_limit_recursion = Some(limit);
+    }
+
+    /// Studies the regular expression to see if additional information can be extracted
+    /// which might speed up matching.
+    ///
+    /// # Return value
+    /// `true` if additional information could be extracted. `false` otherwise.
+    pub fn study(&mut self) -> bool {
+        let no_options: EnumSet<StudyOption> = EnumSet::empty();
+        self.study_with_options(&no_options)
+    }
+
+    /// Studies the regular expression using the given bitwise-OR'd study options `options`
+    /// to see if additional information can be extracted which might speed up matching.
+    ///
+    /// # Argument
+    /// * `options` - Study options. See the libpcre manpages, `man 3 pcre_study`, for more
+    ///   information about each option.
+    ///
+    /// # Return value
+    /// `true` if additional information could be extracted. `false` otherwise.
+    ///
+    /// # Failure
+    /// Fails if libpcre reports an error, for example if it cannot allocate memory.
+    pub fn study_with_options(&mut self, options: &EnumSet<StudyOption>) -> bool {
+        match self.study_internal(options) {
+            Ok(studied) => studied,
+            Err(err) => fail!("pcre_study() failed: {:s}", err)
+        }
+    }
+
+    fn study_internal(&mut self, options: &EnumSet<StudyOption>) -> Result<bool, ~str> {
+        // The current study data is freed once no clone or iterator refers to it.
+        let extra = try!(unsafe { detail::pcre_study(self.code.ptr, options) });
+        self.extra = Arc::new(StudyData { ptr: extra, options: Some(options.clone()) });
+        self.jit_stack = MachineJitStack;
+        self.jit = self.is_jit_compiled();
+        Ok(extra.is_not_null())
+    }
+
+    /// Returns whether the regular expression was successfully JIT-compiled by
+    /// [study_with_options()](#method.study_with_options) with `StudyJitCompile`
+    /// (PCRE_INFO_JIT).
+    ///
+    /// JIT compiling fails if libpcre was built without JIT support, or for patterns which
+    /// use features that the JIT compiler does not support; matching then falls back to
+    /// the interpreter.
+    pub fn is_jit_compiled(&self) -> bool {
+        let jit: c_int = unsafe {
+            fullinfo(self.code.ptr, self.extra.ptr as *detail::pcre_extra, detail::PCRE_INFO_JIT, 0).unwrap()
+        };
+        jit != 0
+    }
+
+    /// Returns the size in bytes of the JIT-compiled code, or 0 if the regular expression
+    /// was not JIT-compiled (PCRE_INFO_JITSIZE).
+    pub fn jit_size(&self) -> uint {
+        let size: size_t = unsafe {
+            fullinfo(self.code.ptr, self.extra.ptr as *detail::pcre_extra, detail::PCRE_INFO_JITSIZE, 0).unwrap()
+        };
+        size as uint
+    }
+
+    /// Assigns the JIT stack `stack` to the JIT-compiled code of this regular expression.
+    ///
+    /// Studying the regular expression again removes the assignment, and clones of this
+    /// Pcre use the default stack.
+    ///
+    /// # Safety
+    /// A JIT stack can only be used by one match attempt at a time. The caller must make
+    /// sure that no two match attempts using `stack` run at the same time, whether through
+    /// this Pcre (which can be shared, since matching only borrows it), its iterators, or
+    /// other regular expressions that `stack` is assigned to.
+    ///
+    /// # Argument
+    /// * `stack` - The JIT stack.
+    ///
+    /// # Return value
+    /// `true` if the stack was assigned. `false` if the regular expression is not
+    /// JIT-compiled.
+    pub unsafe fn assign_jit_stack(&mut self, stack: Arc<JitStack>) -> bool {
+        if !self.jit {
+            return false;
+        }
+        // The stack is not assigned to the study data with pcre_assign_jit_stack(), since
+        // the study data is shared with clones of this Pcre and outlives the assignment.
+        self.jit_stack = AssignedJitStack(stack);
+        true
+    }
+
+    /// Makes the JIT-compiled code of this regular expression use the JIT stack of the task
+    /// running each match attempt, as set by [JitStack::set_for_task()](struct.JitStack.html#method.set_for_task).
+    /// Tasks without a JIT stack use the default 32K of the machine stack.
+    ///
+    /// # Return value
+    /// `true` if the task's JIT stack will be used. `false` if the regular expression is
+    /// not JIT-compiled.
+    pub fn assign_task_jit_stack(&mut self) -> bool {
+        if !self.jit {
+            return false;
+        }
+        // As with assign_jit_stack(), the shared study data is left alone. The stack of the
+        // task is looked up for each match attempt instead.
+        self.jit_stack = TaskJitStack;
+        true
+    }
+
+    /// Sets the extra options on this pcre.
+    ///
+    /// Only `ExtraMark` and `ExtraCalloutData` are applied. Every match attempt already
+    /// requests the mark and passes its callout data, so neither needs to be set. The other
+    /// options only make sense together with a value, and are ignored: use
+    /// [set_match_limit()](#method.set_match_limit),
+    /// [set_match_limit_recursion()](#method.set_match_limit_recursion) and
+    /// [set_tables()](#method.set_tables) instead, and [study()](#method.study) for the study
+    /// data and JIT-compiled code.
+    ///
+    /// # Argument
+    /// * `options` - Extra Options. See `man pcreapi`  for more info about each option
+    ///   (search for "Extra data for pcre_exec")
+    ///
+    /// # Return value
+    /// Always `true`. The regular expression no longer needs to be studied first.
+    pub fn set_extra_options(&mut self, options: &EnumSet<ExtraOption>) -> bool {
+        for option in options.iter() {
+            match option {
+                ExtraMark | ExtraCalloutData => self.extra_options |= option as c_ulong,
+                _ => ()
+            }
+        }
+        true
+    }
+
+    /// Sets the character tables used when matching, in place of the tables the regular
+    /// expression was compiled with (PCRE_EXTRA_TABLES).
+    ///
+    /// The tables must be equivalent to those used at compile time, which is needed when a
+    /// compiled regular expression was saved and loaded again. To match according to the
+    /// rules of a locale, compile with [compile_with_tables()](#method.compile_with_tables)
+    /// instead.
+    ///
+    /// # Argument
+    /// * `tables` - The character tables, which are kept alive for as long as this Pcre.
+    pub fn set_tables(&mut self, tables: Arc<CharTables>) {
+        self.match_tables = Some(tables);
+    }
+}
+
+impl Clone for Pcre {
+    /// Returns a Pcre sharing the compiled code and study data of this one. The clone uses
+    /// the default JIT stack unless the JIT stack of the task was assigned.
+    fn clone(&self) -> Pcre {
+        Pcre {
+            code: self.code.clone(),
+            extra: self.extra.clone(),
+            capture_count_: self.capture_count_,
+            name_table_: self.name_table_.clone(),
+            utf8: self.utf8,
+            limits: self.limits.clone(),
+            jit: self.jit,
+            jit_stack: match self.jit_stack {
+                AssignedJitStack(_) => MachineJitStack,
+                ref jit_stack => jit_stack.clone()
+            },
+            extra_options: self.extra_options,
+            match_tables: self.match_tables.clone()
+        }
+    }
+}
+
+impl Drop for CompiledCode {
+    fn drop(&mut self) {
+        unsafe {
+            detail::pcre_free(self.ptr as *mut detail::pcre as *mut c_void);
+        }
+        self.ptr = ptr::null();
+    }
+}
+
+impl Drop for StudyData {
+    fn drop(&mut self) {
+        unsafe {
+            detail::pcre_free_study(self.ptr);
+        }
+        self.ptr = ptr::mut_null();
+    }
+}
+
+impl CharTables {
+    /// Builds character tables for the current LC_CTYPE locale of the process.
+    ///
+    /// # Failure
+    /// Fails if libpcre cannot allocate the tables.
+    pub fn new() -> CharTables {
+        let tables = unsafe { detail::pcre_maketables(None) };
+        assert!(tables.is_not_null());
+        CharTables {
+            tables: tables
+        }
+    }
+
+    /// Builds character tables for the locale `locale`, for example `"de_DE.ISO-8859-1"` or
+    /// `"tr_TR.ISO-8859-9"`.
+    ///
+    /// The LC_CTYPE locale of the process is changed while the tables are built and then
+    /// restored. Calls within this crate are serialized, but other code which reads or
+    /// changes the locale at the same time may observe the change.
+    ///
+    /// # Return value
+    /// The character tables, or `None` if the locale is not available.
+    pub fn for_locale(locale: &str) -> Option<CharTables> {
+        let tables = locale.with_c_str(|locale_c_str| unsafe {
+            detail::pcre_maketables(Some(locale_c_str))
+        });
+        if tables.is_null() {
+            None
+        } else {
+            Some(CharTables {
+                tables: tables
+            })
+        }
+    }
+
+    /// Copies saved character tables into a block allocated by `pcre_malloc()`.
+    fn from_bytes(tables: &[u8]) -> CharTables {
+        assert_eq!(tables.len(), detail::PCRE_TABLES_LENGTH);
+        unsafe {
+            let ptr = detail::pcre_malloc(tables.len()) as *mut u8;
+            assert!(ptr.is_not_null());
+            ptr::copy_nonoverlapping_memory(ptr, tables.as_ptr(), tables.len());
+            CharTables {
+                tables: ptr as *c_uchar
+            }
+        }
+    }
+}
+
+impl Drop for CharTables {
+    fn drop(&mut self) {
+        unsafe {
+            detail::pcre_free(self.tables as *mut c_void);
+        }
+        self.tables = ptr::null();
+    }
+}
+
+local_data_key!(TASK_JIT_STACK: JitStack)
+
+impl JitStack {
+    /// Allocates a JIT stack which starts at `start_size` bytes and grows up to `max_size`
+    /// bytes.
+    ///
+    /// # Return value
+    /// The JIT stack, or `None` if it could not be allocated.
+    pub fn new(start_size: uint, max_size: uint) -> Option<JitStack> {
+        let stack = unsafe { detail::pcre_jit_stack_alloc(start_size as c_int, max_size as c_int) };
+        if stack.is_null() {
+            None
+        } else {
+            Some(JitStack {
+                stack: stack
+            })
+        }
+    }
+
+    /// Sets the JIT stack of the current task, used by regular expressions for which
+    /// [assign_task_jit_stack()](struct.Pcre.html#method.assign_task_jit_stack) was called.
+    ///
+    /// # Return value
+    /// The JIT stack previously set for the current task, if any.
+    pub fn set_for_task(stack: JitStack) -> Option<JitStack> {
+        let old_stack = local_data::pop(TASK_JIT_STACK);
+        local_data::set(TASK_JIT_STACK, stack);
+        old_stack
+    }
+}
+
+impl Drop for JitStack {
+    fn drop(&mut self) {
+        unsafe {
+            detail::pcre_jit_stack_free(self.stack);
+        }
+        self.stack = ptr::mut_null();
+    }
+}
+
+impl<'a> Match<'a> {
+    /// Returns the start index within the subject string of capture group `n`.
+    ///
+    /// # Failure
+    /// Fails if capture group `n` does not exist or did not participate in the match.
+    /// See [range()](#method.range) for a non-failing alternative.
+    pub fn group_start(&self, n: uint) -> uint {
+        match self.range(n) {
+            None => fail!("capture group {:u} is not set", n),
+            Some((start, _)) => start
+        }
+    }
+
+    /// Returns the end index within the subject string of capture group `n`.
+    ///
+    /// # Failure
+    /// Fails if capture group `n` does not exist or did not participate in the match.
+    /// See [range()](#method.range) for a non-failing alternative.
+    pub fn group_end(&self, n: uint) -> uint {
+        match self.range(n) {
+            None => fail!("capture group {:u} is not set", n),
+            Some((_, end)) => end
+        }
+    }
+
+    /// Returns the length of the substring for capture group `n`.
+    ///
+    /// # Failure
+    /// Fails if capture group `n` does not exist or did not participate in the match.
+    /// See [range()](#method.range) for a non-failing alternative.
+    pub fn group_len(&self, n: uint) -> uint {
+        match self.range(n) {
+            None => fail!("capture group {:u} is not set", n),
+            Some((start, end)) => end - start
+        }
+    }
+
+    /// Returns the substring for capture group `n` as a slice.
+    ///
+    /// # Failure
+    /// Fails if capture group `n` does not exist or did not participate in the match.
+    /// See [get()](#method.get) for a non-failing alternative.
+    #[inline]
+    pub fn group(&self, n: uint) -> &'a str {
+        match self.get(n) {
+            None => fail!("capture group {:u} is not set", n),
+            Some(group) => group
+        }
+    }
+
+    /// Returns the start and end indices within the subject string of capture group `n`,
+    /// or `None` if the group does not exist or did not participate in the match.
+    ///
+    /// For example, only one of the two groups of `(a)|(b)` is set in any match.
+    pub fn range(&self, n: uint) -> Option<(uint, uint)> {
+        ovector_range(self.partial_ovector.as_slice(), n)
+    }
+
+    /// Returns the substring for capture group `n` as a slice, or `None` if the group does
+    /// not exist or did not participate in the match.
+    #[inline]
+    pub fn get(&self, n: uint) -> Option<&'a str> {
+        self.range(n).map(|(start, end)| self.subject.slice(start, end))
+    }
+
+    /// Creates a `CaptureIterator` over every capture group of the regular expression,
+    /// starting with group 0 and ending with group `capture_count()`.
+    pub fn captures<'m>(&'m self) -> CaptureIterator<'m, 'a> {
+        CaptureIterator {
+            m: self,
+            n: 0u
+        }
+    }
+
+    /// Returns the number of substrings captured.
+    pub fn string_count(&self) -> uint {
+        self.string_count_ as uint
+    }
+
+    /// Returns the name of the last `(*MARK)`, or `(*PRUNE)` or `(*THEN)` with a name,
+    /// passed on the matching path, if any.
+    pub fn mark<'m>(&'m self) -> Option<&'m str> {
+        self.mark.as_ref().map(|mark| mark.as_slice())
+    }
+
+    /// Returns the number of the first capture group named `name` that participated in
+    /// the match.
+    ///
+    /// There can be more than one group named `name` if the PCRE_DUPNAMES option is used
+    /// when compiling the regular expression.
+    fn name_to_group(&self, name: &str) -> Option<uint> {
+        find_name((*self.name_table).as_slice(), name).and_then(|n_vec| self.first_set_group(n_vec))
+    }
+
+    /// Returns the first of the capture groups `n_vec` that participated in the match.
+    fn first_set_group(&self, n_vec: &[uint]) -> Option<uint> {
+        n_vec.iter().find(|n| self.range(**n).is_some()).map(|n| *n)
+    }
+
+    /// Returns the substring for the capture group named `name`, or `None` if there is no
+    /// such group or it did not participate in the match.
+    pub fn group_by_name(&self, name: &str) -> Option<&'a str> {
+        self.name_to_group(name).map(|n| self.group(n))
+    }
+
+    /// Returns the start index within the subject string of the capture group named `name`,
+    /// or `None` if there is no such group or it did not participate in the match.
+    pub fn name_start(&self, name: &str) -> Option<uint> {
+        self.name_to_group(name).map(|n| self.group_start(n))
+    }
+
+    /// Returns the end index within the subject string of the capture group named `name`,
+    /// or `None` if there is no such group or it did not participate in the match.
+    pub fn name_end(&self, name: &str) -> Option<uint> {
+        self.name_to_group(name).map(|n| self.group_end(n))
+    }
+
+    /// Creates a `NamedGroups` iterator over the names of the capture groups and their
+    /// substrings, in name order.
+    pub fn named_groups<'m>(&'m self) -> NamedGroups<'m, 'a> {
+        NamedGroups {
+            m: self,
+            names: self.name_table.iter()
+        }
+    }
+}
+
+impl MatchData {
+    /// Returns the number of substrings captured by the last match attempt, or 0 if it did
+    /// not match.
+    pub fn string_count(&self) -> uint {
+        self.string_count_ as uint
+    }
+
+    /// Returns the start and end indices within the subject string of capture group `n` in
+    /// the last match, or `None` if the last match attempt did not match, or the group does
+    /// not exist or did not participate in the match.
+    pub fn range(&self, n: uint) -> Option<(uint, uint)> {
+        if self.string_count_ == 0 {
+            return None;
+        }
+        ovector_range(self.ovector.slice_to(((self.capture_count + 1) * 2) as uint), n)
+    }
+
+    /// Returns the substring of `subject` for capture group `n` in the last match, or `None`
+    /// if it is not set. See [range()](#method.range).
+    ///
+    /// # Argument
+    /// * `subject` - The subject string of the last match attempt.
+    #[inline]
+    pub fn get<'s>(&self, subject: &'s str, n: uint) -> Option<&'s str> {
+        self.range(n).map(|(start, end)| subject.slice(start, end))
+    }
+
+    /// Returns the name of the last `(*MARK)` passed by the last match attempt, if any.
+    ///
+    /// After a successful match, this is the mark on the matching path. After a partial or
+    /// failed match, it is the last mark passed anywhere in the match process.
+    pub fn mark<'m>(&'m self) -> Option<&'m str> {
+        self.mark.as_ref().map(|mark| mark.as_slice())
+    }
+}
+
+impl<'a> BytesMatch<'a> {
+    /// Returns the start index within the subject byte string of capture group `n`.
+    ///
+    /// # Failure
+    /// Fails if capture group `n` does not exist or did not participate in the match.
+    pub fn group_start(&self, n: uint) -> uint {
+        match self.range(n) {
+            None => fail!("capture group {:u} is not set", n),
+            Some((start, _)) => start
+        }
+    }
+
+    /// Returns the end index within the subject byte string of capture group `n`.
+    ///
+    /// # Failure
+    /// Fails if capture group `n` does not exist or did not participate in the match.
+    pub fn group_end(&self, n: uint) -> uint {
+        match self.range(n) {
+            None => fail!("capture group {:u} is not set", n),
+            Some((_, end)) => end
+        }
+    }
+
+    /// Returns the substring for capture group `n` as a slice.
+    ///
+    /// # Failure
+    /// Fails if capture group `n` does not exist or did not participate in the match.
+    #[inline]
+    pub fn group(&self, n: uint) -> &'a [u8] {
+        match self.get(n) {
+            None => fail!("capture group {:u} is not set", n),
+            Some(group) => group
+        }
+    }
+
+    /// Returns the start and end indices within the subject byte string of capture group
+    /// `n`, or `None` if the group does not exist or did not participate in the match.
+    pub fn range(&self, n: uint) -> Option<(uint, uint)> {
+        ovector_range(self.partial_ovector.as_slice(), n)
+    }
+
+    /// Returns the substring for capture group `n` as a slice, or `None` if the group does
+    /// not exist or did not participate in the match.
+    #[inline]
+    pub fn get(&self, n: uint) -> Option<&'a [u8]> {
+        self.range(n).map(|(start, end)| self.subject.slice(start, end))
+    }
+
+    /// Returns the substring for the capture group named `name`, or `None` if there is no
+    /// such group or it did not participate in the match.
+    pub fn group_by_name(&self, name: &str) -> Option<&'a [u8]> {
+        match find_name((*self.name_table).as_slice(), name) {
+            None => None,
+            Some(n_vec) => n_vec.iter().filter_map(|n| self.get(*n)).next()
+        }
+    }
+
+    /// Returns the number of substrings captured.
+    pub fn string_count(&self) -> uint {
+        self.string_count_ as uint
+    }
+}
+
+impl<'m, 'a> Iterator<Option<&'a str>> for CaptureIterator<'m, 'a> {
+    #[inline]
+    fn next(&mut self) -> Option<Option<&'a str>> {
+        if self.n * 2 >= self.m.partial_ovector.len() {
+            None
+        } else {
+            self.n += 1;
+            Some(self.m.get(self.n - 1))
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        let remaining = self.m.partial_ovector.len() / 2 - self.n;
+        (remaining, Some(remaining))
+    }
+}
+
+impl<'m, 'a> Iterator<(&'m str, Option<&'a str>)> for NamedGroups<'m, 'a> {
+    #[inline]
+    fn next(&mut self) -> Option<(&'m str, Option<&'a str>)> {
+        let m = self.m;
+        self.names.next().map(|&(ref name, ref n_vec)| (name.as_slice(), m.first_set_group(n_vec.as_slice()).map(|n| m.group(n))))
+    }
+}
+
+impl<'t> Replacer for &'t str {
+    fn reg_replace<'r>(&'r mut self, m: &Match) -> MaybeOwned<'r> {
+        if self.contains_char('$') {
+            Owned(expand_template(*self, m))
+        } else {
+            Slice(*self)
+        }
+    }
+}
+
+impl<'f> Replacer for 'f |&Match| -> ~str {
+    fn reg_replace<'r>(&'r mut self, m: &Match) -> MaybeOwned<'r> {
+        Owned((*self)(m))
+    }
+}
+
+/// Expands the `$n`, `${n}`, `${name}` and `$$` references in the replacement template
+/// `template` using the capture groups of `m`.
+fn expand_template(template: &str, m: &Match) -> ~str {
+    let bytes = template.as_bytes();
+    let mut result = str::with_capacity(template.len());
+    let mut literal_start = 0u;
+    let mut i = 0u;
+    while i < bytes.len() {
+        if bytes[i] != '$' as u8 || i + 1 == bytes.len() {
+            i += 1;
+            continue;
+        }
+        result.push_str(template.slice(literal_start, i));
+
+        let next = bytes[i + 1];
+        if next == '$' as u8 {
+            result.push_char('$');
+            i += 2;
+        } else if next >= '0' as u8 && next <= '9' as u8 {
+            let mut j = i + 1;
+            let mut n = 0u;
+            while j < bytes.len() && bytes[j] >= '0' as u8 && bytes[j] <= '9' as u8 {
+                n = n * 10 + (bytes[j] - '0' as u8) as uint;
+                j += 1;
+            }
+            result.push_str(m.get(n).unwrap_or(""));
+            i = j;
+        } else if next == '{' as u8 {
+            match template.slice_from(i + 2).find('}') {
+                None => {
+                    // Unterminated reference; keep it as literal text.
+                    result.push_char('$');
+                    i += 1;
+                },
+                Some(len) => {
+                    let name = template.slice(i + 2, i + 2 + len);
+                    match from_str::<uint>(name) {
+                        Some(n) => result.push_str(m.get(n).unwrap_or("")),
+                        None => result.push_str(m.group_by_name(name).unwrap_or(""))
+                    }
+                    i += len + 3;
+                }
+            }
+        } else {
+            result.push_char('$');
+            i += 1;
+        }
+        literal_start = i;
+    }
+    result.push_str(template.slice_from(literal_start));
+
+    result
+}
+
+impl<'a> Clone for MatchIterator<'a> {
+    #[inline]
+    fn clone(&self) -> MatchIterator<'a> {
+        MatchIterator {
+            code: self.code.clone(),
+            extra: self.extra.clone(),
+            capture_count: self.capture_count,
+            name_table: self.name_table.clone(),
+            subject: self.subject,
+            offset: self.offset,
+            options: self.options,
+            limits: self.limits.clone(),
+            match_tables: self.match_tables.clone(),
+            jit: self.jit,
+            jit_stack: self.jit_stack.clone(),
+            ovector: self.ovector.clone(),
+            last_match_empty: self.last_match_empty,
+            crlf_is_newline: self.crlf_is_newline,
+            error: self.error.clone()
+        }
+    }
+}
+
+impl<'a> MatchIterator<'a> {
+    /// Gets the next match, returning an `ExecError` if the match attempt fails.
+    ///
+    /// Once a match attempt has failed, every later call returns the same error.
+    #[inline]
+    pub fn try_next(&mut self) -> Result<Option<Match<'a>>, ExecError> {
+        self.try_next_internal(ptr::mut_null())
+    }
+
+    /// Gets the next match like [try_next()](#method.try_next), calling `callout` at each
+    /// callout point reached while looking for it.
+    /// See [Pcre::exec_with_callout()](struct.Pcre.html#method.exec_with_callout).
+    ///
+    /// # Argument
+    /// * `callout` - The callout function.
+    pub fn try_next_with_callout(&mut self, callout: |&CalloutBlock| -> CalloutResult) -> Result<Option<Match<'a>>, ExecError> {
+        let mut callout = callout;
+        let callout_data = &mut callout as *mut |&CalloutBlock| -> CalloutResult as *mut c_void;
+        self.try_next_internal(callout_data)
+    }
+
+    fn try_next_internal(&mut self, callout_data: *mut c_void) -> Result<Option<Match<'a>>, ExecError> {
+        let mut mark: *mut c_uchar = ptr::mut_null();
+        match try!(self.next_match(callout_data, &mut mark)) {
+            None => Ok(None),
+            Some(rc) => Ok(Some(Match {
+                subject: self.subject,
+                partial_ovector: self.ovector.slice_to(((self.capture_count + 1) * 2) as uint).to_owned(),
+                string_count_: rc,
+                name_table: self.name_table.clone(),
+                mark: mark_name(mark)
+            }))
+        }
+    }
+
+    /// Finds the next match, leaving its offsets in `self.ovector` and its mark in `mark`.
+    /// Returns the return code of libpcre, or `None` if there are no more matches.
+    fn next_match(&mut self, callout_data: *mut c_void, mark: &mut *mut c_uchar) -> Result<Option<c_int>, ExecError> {
+        match self.error {
+            Some(ref err) => return Err(err.clone()),
+            None => ()
+        }
+
+        unsafe {
+            let subject_c_str = self.subject.as_ptr() as *c_char;
+            let mut extra = self.limits.apply(self.extra.ptr as *detail::pcre_extra);
+            apply_match_tables(&mut extra, &self.match_tables);
+            set_mark_location(&mut extra, mark);
+            set_callout_data(&mut extra, callout_data);
+
+            // This follows the global matching loop of pcredemo.c: after an empty match,
+            // look for a non-empty match at the same offset, and failing that, move on
+            // by one character.
+            loop {
+                let mut options = self.options;
+                if self.last_match_empty {
+                    if self.offset as uint == self.subject.len() {
+                        return Ok(None);
+                    }
+                    options.add(ExecNotEmptyAtStart);
+                    options.add(ExecAnchored);
+                }
+
+                *mark = ptr::mut_null();
+                let rc = exec_jit_or_interpreted(self.code.ptr, &extra, self.jit, self.jit_stack.stack(), subject_c_str, self.subject.len() as c_int, self.offset, &options, false, self.ovector.as_mut_slice());
+                if rc >= 0 {
+                    // Update the iterator state.
+                    self.last_match_empty = self.ovector[0] == self.ovector[1];
+                    self.offset = self.ovector[1];
+                    return Ok(Some(rc));
+                } else if rc == detail::PCRE_ERROR_NOMATCH {
+                    if !self.last_match_empty {
+                        return Ok(None);
+                    }
+                    let offset = self.offset as uint;
+                    self.offset = if self.crlf_is_newline && self.subject.slice_from(offset).starts_with("\r\n") {
+                        offset + 2
+                    } else {
+                        self.subject.char_range_at(offset).next
+                    } as c_int;
+                    self.last_match_empty = false;
+                } else {
+                    let err = exec_error(rc, self.ovector.as_slice());
+                    self.error = Some(err.clone());
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    /// Returns the error which ended the iteration, or `None` if no match attempt failed.
+    pub fn error(&self) -> Option<ExecError> {
+        self.error.clone()
+    }
+}
+
+impl<'a> Iterator<Match<'a>> for MatchIterator<'a> {
+    /// Gets the next match.
+    ///
+    /// Returns `None` if a match attempt fails. See [error()](#method.error).
+    #[inline]
+    fn next(&mut self) -> Option<Match<'a>> {
+        match self.try_next() {
+            Ok(opt_m) => opt_m,
+            Err(_) => None
+        }
+    }
+}
+
+impl<'p> DfaMatcher<'p> {
+    /// Returns the size of the workspace, in ints.
+    pub fn workspace_size(&self) -> uint {
+        self.workspace.len()
+    }
+
+    /// Sets the size of the workspace, in ints. The default is 1000.
+    ///
+    /// Patterns with many alternatives or nested repeats need a larger workspace; a match
+    /// attempt with a workspace which is too small fails with `DfaWorkspaceSizeError`.
+    /// Resizing the workspace discards the state kept for [restart()](#method.restart).
+    pub fn set_workspace_size(&mut self, size: uint) {
+        self.workspace = vec::from_elem(size, 0 as c_int);
+    }
+
+    /// Returns whether match attempts stop at the shortest match.
+    pub fn shortest(&self) -> bool {
+        self.shortest
+    }
+
+    /// Sets whether match attempts stop as soon as the shortest match is found
+    /// (PCRE_DFA_SHORTEST), rather than reporting every match. Off by default.
+    pub fn set_shortest(&mut self, shortest: bool) {
+        self.shortest = shortest;
+    }
+
+    /// Returns the maximum number of matches reported by a match attempt.
+    pub fn max_matches(&self) -> uint {
+        self.max_matches
+    }
+
+    /// Sets the maximum number of matches reported by a match attempt. The default is 10.
+    ///
+    /// If more matches are found, then only the longest `max_matches` are reported.
+    pub fn set_max_matches(&mut self, max_matches: uint) {
+        assert!(max_matches > 0);
+        self.max_matches = max_matches;
+    }
+
+    /// Matches the regular expression against a given subject string `subject`.
+    ///
+    /// # Argument
+    /// * `subject` - The subject string.
+    #[inline]
+    pub fn exec<'a>(&mut self, subject: &'a str) -> Result<DfaResult<'a>, ExecError> {
+        let no_options: EnumSet<ExecOption> = EnumSet::empty();
+        self.exec_from_with_options(subject, 0u, &no_options)
+    }
+
+    /// Matches the regular expression against a given subject string `subject` starting at
+    /// offset `startoffset` and using the given bitwise-OR'd matching options `options`.
+    ///
+    /// To match input that arrives in segments, pass `ExecPartialSoft` or `ExecPartialHard`
+    /// and continue a partial match with [restart()](#method.restart).
+    ///
+    /// # Arguments
+    /// * `subject` - The subject string.
+    /// * `startoffset` - Starting offset within `subject` at which to begin looking for
+    ///   a match.
+    /// * `options` - Bitwise-OR'd matching options. See the libpcre manpages,
+    ///   `man 3 pcre_dfa_exec`, for more information.
+    #[inline]
+    pub fn exec_from_with_options<'a>(&mut self, subject: &'a str, startoffset: uint, options: &EnumSet<ExecOption>) -> Result<DfaResult<'a>, ExecError> {
+        self.exec_internal(subject, startoffset, options, false)
+    }
+
+    /// Continues a match attempt which ended in a partial match, using the next segment
+    /// `subject` of the input (PCRE_DFA_RESTART).
+    ///
+    /// Previous segments do not need to be kept: the state of the match attempt is kept in
+    /// the workspace. The offsets of the result are relative to `subject`, and a match only
+    /// includes its part within `subject`.
+    ///
+    /// # Arguments
+    /// * `subject` - The next segment of the input.
+    /// * `options` - Bitwise-OR'd matching options. Pass `ExecPartialSoft` or
+    ///   `ExecPartialHard` again if more segments may follow.
+    #[inline]
+    pub fn restart<'a>(&mut self, subject: &'a str, options: &EnumSet<ExecOption>) -> Result<DfaResult<'a>, ExecError> {
+        self.exec_internal(subject, 0u, options, true)
+    }
+
+    fn exec_internal<'a>(&mut self, subject: &'a str, startoffset: uint, options: &EnumSet<ExecOption>, restart: bool) -> Result<DfaResult<'a>, ExecError> {
+        let mut dfa_options = 0;
+        if self.shortest {
+            dfa_options |= detail::PCRE_DFA_SHORTEST;
+        }
+        if restart {
+            dfa_options |= detail::PCRE_DFA_RESTART;
+        }
+        try!(self.re.check_str_subject(subject, startoffset));
+        let mut ovector: ~[c_int] = vec::from_elem(self.max_matches * 2, 0 as c_int);
+
+        unsafe {
+            let subject_c_str = subject.as_ptr() as *c_char;
+            let mut extra = MatchLimits::new().apply(self.re.extra.ptr as *detail::pcre_extra);
+            apply_match_tables(&mut extra, &self.re.match_tables);
+            let rc = detail::pcre_dfa_exec(self.re.code.ptr, &extra, subject_c_str, subject.len() as c_int, startoffset as c_int, options, dfa_options, false, ovector.as_mut_ptr(), ovector.len() as c_int, self.workspace.as_mut_ptr(), self.workspace.len() as c_int);
+            if rc >= 0 {
+                // "If the vector is too small to hold all the matches, the return
+                // value is zero and the vector is filled with the longest matches."
+                let count = if rc == 0 { self.max_matches } else { rc as uint };
+                Ok(DfaFull(DfaMatch {
+                    subject: subject,
+                    ovector: ovector.slice_to(count * 2).to_owned(),
+                    count: count
+                }))
+            } else if rc == detail::PCRE_ERROR_NOMATCH {
+                Ok(DfaNoMatch)
+            } else if rc == detail::PCRE_ERROR_PARTIAL {
+                Ok(DfaPartial {
+                    start: ovector[0] as uint,
+                    end: ovector[1] as uint
+                })
+            } else {
+                Err(exec_error(rc, ovector.as_slice()))
+            }
+        }
+    }
+}
+
+impl PcreSet {
+    /// Compiles the given regular expressions into a set.
+    ///
+    /// # Argument
+    /// * `patterns` - The regular expressions. Matches are reported by index in this list.
+    pub fn new(patterns: &[&str]) -> Result<PcreSet, SetCompilationError> {
+        let no_options: EnumSet<CompileOption> = EnumSet::empty();
+        let patterns: ~[(&str, EnumSet<CompileOption>)] = patterns.iter().map(|&pattern| (pattern, no_options.clone())).collect();
+        PcreSet::with_options(patterns.as_slice())
+    }
+
+    /// Compiles the given regular expressions, each with its own compile options, into a
+    /// set.
+    ///
+    /// Regular expressions are combined with others compiled with the same options, apart
+    /// from `Caseless`, `Multiline`, `DotAll`, `Extended`, `Extra`, `Ungreedy` and
+    /// `DupNames`, which are set within each alternative.
+    ///
+    /// # Argument
+    /// * `patterns` - The regular expressions and their compile options. Matches are
+    ///   reported by index in this list.
+    pub fn with_options(patterns: &[(&str, EnumSet<CompileOption>)]) -> Result<PcreSet, SetCompilationError> {
+        let mut separate: ~[(uint, Pcre)] = ~[];
+        // The alternatives to combine, grouped by the options which apply to the whole of
+        // the combined regular expression.
+        let mut groups: TreeMap<c_ulong, ~[(uint, ~str, Pcre)]> = TreeMap::new();
+
+        for (index, &(pattern, ref options)) in patterns.iter().enumerate() {
+            let re = match Pcre::compile_with_options(pattern, options) {
+                Ok(re) => re,
+                Err(err) => return Err(SetCompilationError {
+                    index: index,
+                    error: err
+                })
+            };
+            if !can_combine(pattern, options, &re) {
+                separate.push((index, re));
+                continue;
+            }
+
+            let bits = compile_option_bits(options);
+            // The alternative is atomic, so that once its match is recorded and the end
+            // callout fails, libpcre does not backtrack into it looking for other matches.
+            let mut alternative = format!("(?>(?{:s}:", inline_option_letters(bits));
+            alternative.push_str(pattern);
+            // \E ends a \Q quote left open at the end of the pattern.
+            alternative.push_str("\\E))");
+            let outer = bits & !INLINE_OPTION_BITS;
+            // TODO Avoid the double lookup.
+            // https://github.com/mozilla/rust/issues/9068
+            if !groups.contains_key(&outer) {
+                groups.insert(outer, ~[(index, alternative, re)]);
+            } else {
+                groups.find_mut(&outer).unwrap().push((index, alternative, re));
+            }
+        }
+
+        let mut chunks: ~[SetChunk] = ~[];
+        for (outer, alternatives) in groups.move_iter() {
+            let mut chunk: ~[(uint, ~str, Pcre)] = ~[];
+            for alternative in alternatives.move_iter() {
+                chunk.push(alternative);
+                if chunk.len() == MAX_SET_CHUNK_SIZE {
+                    combine(outer, chunk, &mut chunks, &mut separate);
+                    chunk = ~[];
+                }
+            }
+            combine(outer, chunk, &mut chunks, &mut separate);
+        }
+
+        Ok(PcreSet {
+            chunks: chunks,
+            separate: separate,
+            len: patterns.len()
+        })
+    }
+
+    /// Returns the number of regular expressions in the set.
+    pub fn len(&self) -> uint {
+        self.len
+    }
+
+    /// Returns `true` if any regular expression of the set matches `subject`.
+    pub fn is_match(&self, subject: &str) -> Result<bool, ExecError> {
+        let found = try!(self.find(subject, true));
+        Ok(found.iter().any(|range| range.is_some()))
+    }
+
+    /// Returns the indices of the regular expressions which match `subject`, in
+    /// increasing order.
+    pub fn matches(&self, subject: &str) -> Result<~[uint], ExecError> {
+        let found = try!(self.find(subject, false));
+        Ok(found.iter().enumerate().filter_map(|(index, range)| range.map(|_| index)).collect())
+    }
+
+    /// Returns the regular expressions which match `subject` with the offsets of their
+    /// leftmost matches, in increasing order of index.
+    pub fn match_ranges(&self, subject: &str) -> Result<~[SetMatch], ExecError> {
+        let found = try!(self.find(subject, false));
+        Ok(found.iter().enumerate().filter_map(|(index, range)| {
+            range.map(|(start, end)| SetMatch {
+                index: index,
+                start: start,
+                end: end
+            })
+        }).collect())
+    }
+
+    /// Finds the leftmost match of each regular expression, stopping at the first match
+    /// found if `first_only` is `true`.
+    fn find(&self, subject: &str, first_only: bool) -> Result<~[Option<(uint, uint)>], ExecError> {
+        let no_options: EnumSet<ExecOption> = EnumSet::empty();
+        let mut found: ~[Option<(uint, uint)>] = vec::from_elem(self.len, None);
+
+        for chunk in self.chunks.iter() {
+            let indices = &chunk.indices;
+            let mut remaining = indices.len();
+            // Every alternative is made to fail after recording its match, so that libpcre
+            // goes on to try the others. Alternatives which have already matched are skipped.
+            let result = chunk.re.exec_with_callout(subject, 0u, &no_options, |block| {
+                let index = indices[block.callout_number() / 2];
+                if found[index].is_some() {
+                    CalloutFail
+                } else if block.callout_number() % 2 == 0 {
+                    CalloutContinue
+                } else {
+                    found[index] = Some((block.start_match(), block.current_position()));
+                    remaining -= 1;
+                    if first_only || remaining == 0 { CalloutAbort } else { CalloutFail }
+                }
+            });
+            match result {
+                Ok(_) | Err(CalloutAbortedError) => (),
+                Err(err) => return Err(err)
+            }
+            if first_only && remaining < indices.len() {
+                return Ok(found);
+            }
+        }
+
+        for &(index, ref re) in self.separate.iter() {
+            match try!(re.exec(subject)) {
+                None => (),
+                Some(m) => {
+                    found[index] = Some((m.group_start(0), m.group_end(0)));
+                    if first_only {
+                        return Ok(found);
+                    }
+                }
+            }
+        }
+
+        Ok(found)
+    }
+}
+
+impl SetCompilationError {
+    /// Returns the index of the regular expression which failed to compile.
+    pub fn index(&self) -> uint {
+        self.index
+    }
+
+    /// Returns the compilation error.
+    pub fn error<'a>(&'a self) -> &'a CompilationError {
+        &self.error
+    }
+}
+
+impl fmt::Show for SetCompilationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f.buf, "pattern {:u}: {}", self.index, self.error)
+    }
+}
+
+impl PatternCache {
+    /// Creates a cache which holds up to `capacity` compiled regular expressions.
+    pub fn new(capacity: uint) -> PatternCache {
+        PatternCache {
+            state: MutexArc::new(CacheState {
+                cache: LruCache::new(capacity),
+                stats: CacheStats {
+                    hits: 0u,
+                    misses: 0u,
+                    evictions: 0u
+                }
+            })
+        }
+    }
+
+    /// Returns the regular expression compiled from `pattern`, compiling it if it is not
+    /// in the cache.
+    ///
+    /// The returned `Pcre` is a clone of the cached one, sharing its compiled code and
+    /// study data.
+    pub fn compile(&self, pattern: &str) -> Result<Pcre, CompilationError> {
+        let no_options: EnumSet<CompileOption> = EnumSet::empty();
+        self.compile_with_options(pattern, &no_options)
+    }
+
+    /// Returns the regular expression compiled from `pattern` with the options `options`,
+    /// compiling it if it is not in the cache. See [compile()](#method.compile).
+    pub fn compile_with_options(&self, pattern: &str, options: &EnumSet<CompileOption>) -> Result<Pcre, CompilationError> {
+        match self.lookup(pattern, options, None) {
+            Ok(re) => Ok(re),
+            Err(CompileError(err)) => Err(err),
+            Err(_) => unreachable!()
+        }
+    }
+
+    /// Returns the regular expression compiled from `pattern` with the options `options`
+    /// and studied with the options `study_options`, compiling and studying it if it is
+    /// not in the cache. See [compile()](#method.compile).
+    ///
+    /// A regular expression studied with `StudyJitCompile` is JIT-compiled once and the
+    /// JIT-compiled code is shared by every clone.
+    ///
+    /// # Return value
+    /// The regular expression, or `CompileError` if it could not be compiled, or
+    /// `StudyError` if libpcre failed to study it. Nothing is cached on failure.
+    pub fn compile_and_study(&self, pattern: &str, options: &EnumSet<CompileOption>, study_options: &EnumSet<StudyOption>) -> Result<Pcre, BuildError> {
+        self.lookup(pattern, options, Some(study_options))
+    }
+
+    fn lookup(&self, pattern: &str, options: &EnumSet<CompileOption>, study_options: Option<&EnumSet<StudyOption>>) -> Result<Pcre, BuildError> {
+        let key = CacheKey {
+            pattern: pattern.to_owned(),
+            compile_options: compile_option_bits(options),
+            study_options: study_options.map(|study_options| {
+                study_options.iter().fold(0u, |bits, option| bits | option as uint)
+            })
+        };
+
+        let cached = self.state.access(|state| {
+            let cached = state.cache.get(&key).map(|re| re.clone());
+            match cached {
+                Some(_) => state.stats.hits += 1,
+                None => state.stats.misses += 1
+            }
+            cached
+        });
+        match cached {
+            Some(re) => return Ok(re),
+            None => ()
+        }
+
+        // The lock is not held while compiling, so another task may compile the same
+        // regular expression at the same time. The last one to finish is kept.
+        let mut re = match Pcre::compile_with_options(pattern, options) {
+            Err(err) => return Err(CompileError(err)),
+            Ok(re) => re
+        };
+        match study_options {
+            None => (),
+            Some(study_options) => match re.study_internal(study_options) {
+                Err(err) => return Err(StudyError(err)),
+                Ok(_) => ()
+            }
+        }
+        let result = re.clone();
+        // The closure cannot move its captures, so the entry is taken out of an Option.
+        let mut entry = Some((key, re));
+        self.state.access(|state| {
+            let (key, re) = entry.take_unwrap();
+            let present = state.cache.get(&key).is_some();
+            if !present && state.cache.len() == state.cache.capacity() {
+                state.stats.evictions += 1;
+            }
+            state.cache.put(key, re);
+        });
+        Ok(result)
+    }
+
+    /// Returns the number of regular expressions in the cache.
+    pub fn len(&self) -> uint {
+        self.state.access(|state| state.cache.len())
+    }
+
+    /// Returns the maximum number of regular expressions in the cache.
+    pub fn capacity(&self) -> uint {
+        self.state.access(|state| state.cache.capacity())
+    }
+
+    /// Changes the maximum number of regular expressions in the cache, evicting the least
+    /// recently used ones if there are more than `capacity`.
+    pub fn set_capacity(&self, capacity: uint) {
+        self.state.access(|state| {
+            let len = state.cache.len();
+            if len > capacity {
+                state.stats.evictions += len - capacity;
             }
-            if options.contains_elem(ExtraMark) {
-                (*self.extra).mark = &mut self.mark as *mut *mut u8;
+            state.cache.change_capacity(capacity);
+        })
+    }
+
+    /// Removes every regular expression from the cache. The statistics are kept.
+    pub fn clear(&self) {
+        self.state.access(|state| state.cache.clear())
+    }
+
+    /// Returns the hit, miss and eviction counts of the cache.
+    pub fn stats(&self) -> CacheStats {
+        self.state.access(|state| state.stats.clone())
+    }
+}
+
+impl PcreBuilder {
+    /// Creates a builder for the regular expression `pattern` with no options set.
+    pub fn new(pattern: &str) -> PcreBuilder {
+        PcreBuilder {
+            pattern: pattern.to_owned(),
+            options: EnumSet::empty(),
+            study_options: None,
+            limits: MatchLimits::new(),
+            extra_options: EnumSet::empty(),
+            tables: None,
+            bad_flag: None
+        }
+    }
+
+    /// Adds the compile option `option`.
+    pub fn option(mut self, option: CompileOption) -> PcreBuilder {
+        self.options.add(option);
+        self
+    }
+
+    /// Matches letters caselessly (PCRE_CASELESS, `i`).
+    pub fn caseless(self) -> PcreBuilder {
+        self.option(Caseless)
+    }
+
+    /// Makes `^` and `$` match at newlines within the subject (PCRE_MULTILINE, `m`).
+    pub fn multiline(self) -> PcreBuilder {
+        self.option(Multiline)
+    }
+
+    /// Makes `.` match newlines (PCRE_DOTALL, `s`).
+    pub fn dotall(self) -> PcreBuilder {
+        self.option(DotAll)
+    }
+
+    /// Ignores whitespace and `#` comments in the pattern (PCRE_EXTENDED, `x`).
+    pub fn extended(self) -> PcreBuilder {
+        self.option(Extended)
+    }
+
+    /// Sets the newline convention, replacing any newline option set before.
+    ///
+    /// # Argument
+    /// * `newline` - One of `NewlineCR`, `NewlineLF`, `NewlineCRLF`, `NewlineAny` and
+    ///   `NewlineAnyCRLF`.
+    ///
+    /// # Failure
+    /// Fails if `newline` is not a newline option.
+    pub fn newline(mut self, newline: CompileOption) -> PcreBuilder {
+        let mut newlines: EnumSet<CompileOption> = EnumSet::empty();
+        for &option in [NewlineCR, NewlineLF, NewlineCRLF, NewlineAny, NewlineAnyCRLF].iter() {
+            newlines.add(option);
+        }
+        assert!(newlines.contains_elem(newline), "not a newline option");
+        self.options = self.options - newlines;
+        self.option(newline)
+    }
+
+    /// Sets the compile options given as Perl flags, for example `"imsx"`.
+    ///
+    /// The flags are `i` (Caseless), `m` (Multiline), `s` (DotAll), `x` (Extended),
+    /// `X` (Extra), `U` (Ungreedy) and `J` (DupNames), as in `(?imsxXUJ)` within a
+    /// pattern. An unknown flag makes `build()` return `UnknownFlagError`.
+    pub fn flags(mut self, flags: &str) -> PcreBuilder {
+        for c in flags.chars() {
+            let option = match c {
+                'i' => Caseless,
+                'm' => Multiline,
+                's' => DotAll,
+                'x' => Extended,
+                'X' => Extra,
+                'U' => Ungreedy,
+                'J' => DupNames,
+                _ => {
+                    if self.bad_flag.is_none() {
+                        self.bad_flag = Some(c);
+                    }
+                    continue;
+                }
+            };
+            self.options.add(option);
+        }
+        self
+    }
+
+    /// Studies the regular expression after compiling it.
+    pub fn study(mut self) -> PcreBuilder {
+        if self.study_options.is_none() {
+            self.study_options = Some(EnumSet::empty());
+        }
+        self
+    }
+
+    /// Studies the regular expression with `StudyJitCompile`. If JIT compiling is not
+    /// supported, the regular expression is still studied and is matched by the
+    /// interpreter.
+    pub fn jit(self) -> PcreBuilder {
+        let mut builder = self.study();
+        builder.study_options.get_mut_ref().add(StudyJitCompile);
+        builder
+    }
+
+    /// Sets the match limit. See [Pcre::set_match_limit()](struct.Pcre.html#method.set_match_limit).
+    pub fn match_limit(mut self, limit: uint) -> PcreBuilder {
+        self.limits.match_limit = Some(limit);
+        self
+    }
+
+    /// Sets the recursion limit. See
+    /// [Pcre::set_match_limit_recursion()](struct.Pcre.html#method.set_match_limit_recursion).
+    pub fn match_limit_recursion(mut self, limit: uint) -> PcreBuilder {
+        self.limits.match_limit_recursion = Some(limit);
+        self
+    }
+
+    /// Sets extra options. See
+    /// [Pcre::set_extra_options()](struct.Pcre.html#method.set_extra_options).
+    pub fn extra_options(mut self, options: &EnumSet<ExtraOption>) -> PcreBuilder {
+        self.extra_options = self.extra_options | *options;
+        self
+    }
+
+    /// Compiles the regular expression with the character tables `tables`. See
+    /// [Pcre::compile_with_tables()](struct.Pcre.html#method.compile_with_tables).
+    pub fn tables(mut self, tables: Arc<CharTables>) -> PcreBuilder {
+        self.tables = Some(tables);
+        self
+    }
+
+    /// Compiles, and if requested studies, the regular expression.
+    pub fn build(&self) -> Result<Pcre, BuildError> {
+        match self.bad_flag {
+            Some(c) => return Err(UnknownFlagError(c)),
+            None => ()
+        }
+        let mut re = match Pcre::compile_internal(self.pattern.as_bytes(), &self.options, true, self.tables.clone()) {
+            Ok(re) => re,
+            Err(err) => return Err(CompileError(err))
+        };
+        match self.study_options {
+            None => (),
+            Some(ref study_options) => match re.study_internal(study_options) {
+                Ok(_) => (),
+                Err(err) => return Err(StudyError(err))
             }
-            (*self.extra).flags |= options.iter().fold(0, 
-                |converted_options, option| converted_options | (option as c_int)) as u64;
-            true
         }
+        re.limits = self.limits.clone();
+        re.set_extra_options(&self.extra_options);
+        Ok(re)
     }
 }
 
-impl Drop for Pcre {
-    fn drop(&mut self) {
-        unsafe {
-            if detail::pcre_refcount(self.code as *mut detail::pcre, -1) == 0 {
-                detail::pcre_free_study(self.extra as *mut detail::pcre_extra);
-                detail::pcre_free(self.code as *mut detail::pcre as *mut c_void);
-            }
-            self.extra = ptr::mut_null();
-            self.code = ptr::null();
+impl<'a> DfaMatch<'a> {
+    /// Returns the start index within the subject string shared by all of the matches.
+    pub fn start(&self) -> uint {
+        self.ovector[0] as uint
+    }
+
+    /// Returns the number of matches.
+    pub fn count(&self) -> uint {
+        self.count
+    }
+
+    /// Returns the end index within the subject string of match `i`, where match 0 is
+    /// the longest.
+    ///
+    /// # Failure
+    /// Fails if `i` is not less than `count()`.
+    pub fn end(&self, i: uint) -> uint {
+        assert!(i < self.count);
+        self.ovector[i * 2 + 1] as uint
+    }
+
+    /// Returns the substring of match `i`, or `None` if `i` is not less than `count()`.
+    pub fn get(&self, i: uint) -> Option<&'a str> {
+        if i < self.count {
+            Some(self.subject.slice(self.start(), self.end(i)))
+        } else {
+            None
         }
     }
+
+    /// Returns the substring of the longest match.
+    pub fn longest(&self) -> &'a str {
+        self.get(0u).unwrap()
+    }
 }
 
-impl<'a> Match<'a> {
-    /// Returns the start index within the subject string of capture group `n`.
-    pub fn group_start(&self, n: uint) -> uint {
-        self.partial_ovector[(n * 2) as uint] as uint
+/// Returns whether JIT-compiled code can be run with the matching options `options`.
+///
+/// "The only pcre_exec() options that are supported for JIT execution are
+/// PCRE_NO_UTF8_CHECK, PCRE_NOTBOL, PCRE_NOTEOL, PCRE_NOTEMPTY, PCRE_NOTEMPTY_ATSTART,
+/// PCRE_PARTIAL_HARD, and PCRE_PARTIAL_SOFT."
+/// http://pcre.org/pcre.txt
+fn jit_supports_options(options: &EnumSet<ExecOption>) -> bool {
+    options.iter().all(|option| match option {
+        ExecNotBol | ExecNotEol | ExecNotEmpty | ExecNotEmptyAtStart | ExecPartialSoft | ExecPartialHard => true,
+        _ => false
+    })
+}
+
+/// Runs a match attempt with `pcre_jit_exec()` on the JIT stack `jstack` if `jit` is set,
+/// `jstack` is not null and the JIT-compiled code supports it, and with `pcre_exec()`
+/// otherwise. `pcre_exec()` also runs JIT-compiled code, on 32K of the machine stack.
+unsafe fn exec_jit_or_interpreted(code: *detail::pcre, extra: &detail::pcre_extra, jit: bool, jstack: *mut detail::pcre_jit_stack, subject: *c_char, length: c_int, startoffset: c_int, options: &EnumSet<ExecOption>, check_utf8: bool, ovector: &mut [c_int]) -> c_int {
+    // pcre_jit_exec() skips the checks of pcre_exec(). If the start offset or the subject
+    // is invalid, pcre_exec() is left to report the error.
+    let valid = startoffset >= 0 && startoffset <= length
+        && (!check_utf8 || vec::raw::buf_as_slice(subject as *u8, length as uint, |bytes| str::is_utf8(bytes)));
+    if jit && jstack.is_not_null() && valid && jit_supports_options(options) {
+        let rc = detail::pcre_jit_exec(code, extra, subject, length, startoffset, options, ovector.as_mut_ptr(), ovector.len() as c_int, jstack);
+        // The matching mode might not have been JIT-compiled, in which case pcre_exec()
+        // uses the interpreter.
+        if rc != detail::PCRE_ERROR_JIT_BADOPTION {
+            return rc;
+        }
     }
+    detail::pcre_exec(code, extra, subject, length, startoffset, options, check_utf8, ovector.as_mut_ptr(), ovector.len() as c_int)
+}
 
-    /// Returns the end index within the subject string of capture group `n`.
-    pub fn group_end(&self, n: uint) -> uint {
-        self.partial_ovector[(n * 2 + 1) as uint] as uint
+/// Returns the JIT stack of the current task, or null if it has none.
+fn task_jit_stack() -> *mut detail::pcre_jit_stack {
+    local_data::get(TASK_JIT_STACK, |opt_stack| match opt_stack {
+        None => ptr::mut_null(),
+        Some(stack) => stack.stack
+    })
+}
+
+impl JitStackAssignment {
+    /// Returns the JIT stack to pass to `pcre_jit_exec()`. Null means that there is no such
+    /// stack, in which case `pcre_exec()` must be used instead.
+    fn stack(&self) -> *mut detail::pcre_jit_stack {
+        match *self {
+            MachineJitStack => ptr::mut_null(),
+            AssignedJitStack(ref stack) => stack.stack,
+            TaskJitStack => task_jit_stack()
+        }
     }
+}
 
-    /// Returns the length of the substring for capture group `n`.
-    pub fn group_len(&self, n: uint) -> uint {
-        let group_offsets = self.partial_ovector.slice_from((n * 2) as uint);
-        (group_offsets[1] - group_offsets[0]) as uint
+/// Converts the return code `rc` of a match attempt which did not match into a
+/// `PartialMatchResult`, reading the offsets of a partial match from `ovector`.
+fn unmatched_result<'a>(rc: c_int, ovector: &[c_int]) -> PartialMatchResult<'a> {
+    if rc == detail::PCRE_ERROR_PARTIAL {
+        // "If PCRE_ERROR_PARTIAL is returned, the first two elements of the
+        // ovector are set to the offsets of the partial match."
+        Partial {
+            start: ovector[0] as uint,
+            end: ovector[1] as uint
+        }
+    } else {
+        NoMatch
     }
+}
 
-    /// Returns the substring for capture group `n` as a slice.
-    #[inline]
-    pub fn group(&self, n: uint) -> &'a str {
-        let group_offsets = self.partial_ovector.slice_from((n * 2) as uint);
-        let start = group_offsets[0];
-        let end = group_offsets[1];
-        self.subject.slice(start as uint, end as uint)
+/// Makes the match attempt using `extra` use the character tables `tables`, if any.
+fn apply_match_tables(extra: &mut detail::pcre_extra, tables: &Option<Arc<CharTables>>) {
+    match *tables {
+        None => extra.flags &= !detail::PCRE_EXTRA_TABLES,
+        Some(ref tables) => {
+            extra.flags |= detail::PCRE_EXTRA_TABLES;
+            extra.tables = tables.tables;
+        }
     }
+}
 
-    /// Returns the number of substrings captured.
-    pub fn string_count(&self) -> uint {
-        self.string_count_ as uint
+/// Makes libpcre store the name of the last `(*MARK)` passed by a match attempt in `mark`.
+fn set_mark_location(extra: &mut detail::pcre_extra, mark: &mut *mut c_uchar) {
+    extra.flags |= detail::PCRE_EXTRA_MARK;
+    extra.mark = mark as *mut *mut c_uchar;
+}
+
+/// Copies the mark name `mark`, which points into the compiled regular expression.
+fn mark_name(mark: *mut c_uchar) -> Option<~str> {
+    if mark.is_null() {
+        None
+    } else {
+        unsafe { Some(str::raw::from_c_str(mark as *c_char)) }
     }
 }
 
-impl<'a> Clone for MatchIterator<'a> {
-    #[inline]
-    fn clone(&self) -> MatchIterator<'a> {
-        unsafe {
-            MatchIterator {
-                code: { detail::pcre_refcount(self.code as *mut detail::pcre, 1); self.code },
-                extra: self.extra,
-                capture_count: self.capture_count,
-                subject: self.subject,
-                subject_cstring: self.subject.to_c_str_unchecked(),
-                offset: self.offset,
-                options: self.options,
-                ovector: self.ovector.clone()
+/// Converts the result of a match attempt into the result of a method which only reports
+/// complete matches.
+fn full_match<'a>(result: Result<PartialMatchResult<'a>, ExecError>) -> Result<Option<Match<'a>>, ExecError> {
+    match result {
+        Err(err) => Err(err),
+        Ok(Full(m)) => Ok(Some(m)),
+        Ok(Partial { .. }) => Err(PartialMatchError),
+        Ok(NoMatch) => Ok(None)
+    }
+}
+
+/// Sets the callout data of the extra block `extra` to `callout_data`, a pointer to a
+/// callout closure, unless it is null.
+///
+/// This also makes sure that libpcre's `pcre_callout` hook is installed.
+unsafe fn set_callout_data(extra: &mut detail::pcre_extra, callout_data: *mut c_void) {
+    if callout_data.is_null() {
+        return;
+    }
+
+    static mut INSTALL_CALLOUT: Once = ONCE_INIT;
+    INSTALL_CALLOUT.doit(|| {
+        detail::pcre_set_callout(callout_trampoline);
+    });
+
+    extra.flags |= detail::PCRE_EXTRA_CALLOUT_DATA;
+    extra.callout_data = callout_data;
+}
+
+/// The `pcre_callout` hook, which calls the callout closure passed as the callout data of
+/// the current match attempt.
+extern "C" fn callout_trampoline(block: *mut detail::pcre_callout_block) -> c_int {
+    unsafe {
+        let callout_data = (*block).callout_data;
+        if callout_data.is_null() {
+            // A callout point was reached in a call without a callout closure.
+            return 0;
+        }
+        let callout = callout_data as *mut |&CalloutBlock| -> CalloutResult;
+        let callout_block = CalloutBlock { block: &*block };
+        match (*callout)(&callout_block) {
+            CalloutContinue => 0,
+            CalloutFail => 1,
+            CalloutAbort => detail::PCRE_ERROR_CALLOUT
+        }
+    }
+}
+
+/// Converts a negative return code of `pcre_exec()`, other than PCRE_ERROR_NOMATCH, into
+/// an `ExecError`.
+fn exec_error(rc: c_int, ovector: &[c_int]) -> ExecError {
+    if rc == detail::PCRE_ERROR_MATCHLIMIT {
+        MatchLimitError
+    } else if rc == detail::PCRE_ERROR_RECURSIONLIMIT {
+        RecursionLimitError
+    } else if rc == detail::PCRE_ERROR_BADOFFSET {
+        BadOffsetError
+    } else if rc == detail::PCRE_ERROR_BADUTF8_OFFSET {
+        BadUtf8OffsetError
+    } else if rc == detail::PCRE_ERROR_BADUTF8 || rc == detail::PCRE_ERROR_SHORTUTF8 {
+        // "If the ovector size is 2 or greater, the byte offset of the start of the
+        // invalid UTF-8 character is placed in the first element, and a reason code
+        // is placed in the second element."
+        // http://pcre.org/pcre.txt
+        BadUtf8Error(Utf8Error {
+            code: rc,
+            offset: ovector[0] as uint,
+            reason: ovector[1]
+        })
+    } else if rc == detail::PCRE_ERROR_JIT_STACKLIMIT {
+        JitStackLimitError
+    } else if rc == detail::PCRE_ERROR_NOMEMORY {
+        NoMemoryError
+    } else if rc == detail::PCRE_ERROR_INTERNAL {
+        InternalError
+    } else if rc == detail::PCRE_ERROR_CALLOUT {
+        CalloutAbortedError
+    } else if rc == detail::PCRE_ERROR_PARTIAL {
+        PartialMatchError
+    } else if rc == detail::PCRE_ERROR_DFA_WSSIZE {
+        DfaWorkspaceSizeError
+    } else if rc == detail::PCRE_ERROR_DFA_BADRESTART {
+        DfaBadRestartError
+    } else if rc == detail::PCRE_ERROR_JIT_BADOPTION {
+        JitBadOptionError
+    } else {
+        OtherExecError(rc as int)
+    }
+}
+
+/// Converts the error code set by `pcre_compile2()` into a `CompileErrorKind`.
+fn compile_error_kind(code: c_int) -> CompileErrorKind {
+    match code {
+        1 => BackslashAtEndError,
+        2 => BackslashCAtEndError,
+        3 => UnrecognizedEscapeError,
+        4 => QuantifierOutOfOrderError,
+        5 => QuantifierTooBigError,
+        6 => MissingBracketError,
+        7 => InvalidClassEscapeError,
+        8 => RangeOutOfOrderError,
+        9 => NothingToRepeatError,
+        11 => UnexpectedRepeatError,
+        12 => UnrecognizedGroupError,
+        13 => PosixClassOutsideClassError,
+        14 => MissingParenthesisError,
+        15 => NonexistentSubpatternError,
+        17 => UnknownOptionBitsError,
+        18 => MissingCommentEndError,
+        20 => PatternTooLargeError,
+        21 => CompileMemoryError,
+        22 => UnmatchedParenthesesError,
+        23 => CodeOverflowError,
+        24 => UnrecognizedAfterLessThanError,
+        25 => LookbehindNotFixedLengthError,
+        26 => MalformedConditionError,
+        27 => ConditionBranchesError,
+        28 => AssertionExpectedError,
+        29 => MalformedRecursionError,
+        30 => UnknownPosixClassError,
+        31 => PosixCollatingElementError,
+        32 => NoUtfSupportError,
+        34 => CharacterValueTooLargeError,
+        35 => InvalidConditionError,
+        36 => BackslashCInLookbehindError,
+        37 => UnsupportedEscapeError,
+        38 => CalloutNumberTooBigError,
+        39 => MissingCalloutEndError,
+        40 => RecursiveLoopError,
+        41 => UnrecognizedAfterPError,
+        42 => MissingNameTerminatorError,
+        43 => DuplicateNameError,
+        44 => InvalidUtf8PatternError,
+        45 => NoPropertySupportError,
+        46 => MalformedPropertyError,
+        47 => UnknownPropertyError,
+        48 => NameTooLongError,
+        49 => TooManyNamesError,
+        51 => OctalValueTooLargeError,
+        52 => WorkspaceOverrunError,
+        53 => ReferencedSubpatternNotFoundError,
+        54 => DefineBranchesError,
+        55 => RepeatedDefineError,
+        56 => InconsistentNewlineError,
+        57 => MalformedBackslashGError,
+        58 => ZeroReferenceError,
+        59 => VerbArgumentError,
+        60 => UnknownVerbError,
+        61 => NumberTooBigError,
+        62 => SubpatternNameExpectedError,
+        63 => DigitExpectedError,
+        64 => JavaScriptBracketError,
+        65 => DifferentNamesError,
+        66 => MarkArgumentError,
+        67 => NoUnicodePropertySupportError,
+        68 => BackslashCNonAsciiError,
+        69 => MalformedBackslashKError,
+        70 => UnknownOpcodeError,
+        71 => BackslashNInClassError,
+        72 => TooManyForwardReferencesError,
+        73 => SurrogateCodePointError,
+        74 => InvalidUtf16PatternError,
+        75 => VerbNameTooLongError,
+        76 => BackslashUValueTooLargeError,
+        77 => InvalidUtf32PatternError,
+        78 => UtfDisabledError,
+        79 => NonHexCharacterError,
+        80 => NonOctalCharacterError,
+        81 => MissingOctalBraceError,
+        82 => ParenthesesTooDeepError,
+        83 => InvalidClassRangeError,
+        84 => NameStartsWithDigitError,
+        85 => ParenthesesStackCheckError,
+        86 => MissingDigitsError,
+        _ => OtherCompileError(code as int)
+    }
+}
+
+/// Returns the offsets of capture group `n` stored in `ovector`, or `None` if the group
+/// does not exist or is unset.
+fn ovector_range(ovector: &[c_int], n: uint) -> Option<(uint, uint)> {
+    if n * 2 + 1 >= ovector.len() {
+        return None;
+    }
+    // libpcre sets both offsets of an unset capture group to -1.
+    let start = ovector[n * 2];
+    let end = ovector[n * 2 + 1];
+    if start < 0 || end < 0 {
+        None
+    } else {
+        Some((start as uint, end as uint))
+    }
+}
+
+/// Queries the field `what` of the compiled regular expression `code` and its study data
+/// `extra`. `initial` is the initial value of the field, which has type `T`.
+unsafe fn fullinfo<T>(code: *detail::pcre, extra: *detail::pcre_extra, what: detail::fullinfo_field, initial: T) -> Result<T, c_int> {
+    let mut value = initial;
+    match detail::pcre_fullinfo(code, extra, what, &mut value as *mut T as *mut c_void) {
+        Ok(()) => Ok(value),
+        Err(rc) => Err(rc)
+    }
+}
+
+/// Queries a field of `pcre_fullinfo()` which is not supported by every libpcre version
+/// and may be unset, returning `None` in either case.
+unsafe fn optional_fullinfo<T>(code: *detail::pcre, extra: *detail::pcre_extra, what: detail::fullinfo_field, initial: T) -> Result<Option<T>, c_int> {
+    match fullinfo(code, extra, what, initial) {
+        Ok(value) => Ok(Some(value)),
+        Err(rc) if rc == detail::PCRE_ERROR_BADOPTION || rc == detail::PCRE_ERROR_UNSET => Ok(None),
+        Err(rc) => Err(rc)
+    }
+}
+
+/// Reads all of the fields of `PatternInfo`.
+unsafe fn pattern_info(code: *detail::pcre, extra: *detail::pcre_extra) -> Result<PatternInfo, c_int> {
+    let options: c_ulong = try!(fullinfo(code, extra, detail::PCRE_INFO_OPTIONS, 0));
+    let size: size_t = try!(fullinfo(code, extra, detail::PCRE_INFO_SIZE, 0));
+    let study_size: size_t = try!(fullinfo(code, extra, detail::PCRE_INFO_STUDYSIZE, 0));
+    let capture_count: c_int = try!(fullinfo(code, extra, detail::PCRE_INFO_CAPTURECOUNT, 0));
+    let backref_max: c_int = try!(fullinfo(code, extra, detail::PCRE_INFO_BACKREFMAX, 0));
+    let first_character: u32 = try!(fullinfo(code, extra, detail::PCRE_INFO_FIRSTCHARACTER, 0));
+    // 0 if there is no first character, 1 if there is, and 2 if matches start at the start
+    // of the subject or after a newline.
+    let first_character_flags: c_int = try!(fullinfo(code, extra, detail::PCRE_INFO_FIRSTCHARACTERFLAGS, 0));
+    let first_table: *u8 = try!(fullinfo(code, extra, detail::PCRE_INFO_FIRSTTABLE, ptr::null()));
+    let required_character: u32 = try!(fullinfo(code, extra, detail::PCRE_INFO_REQUIREDCHAR, 0));
+    let required_character_flags: c_int = try!(fullinfo(code, extra, detail::PCRE_INFO_REQUIREDCHARFLAGS, 0));
+    let min_length: c_int = try!(fullinfo(code, extra, detail::PCRE_INFO_MINLENGTH, 0));
+    let max_lookbehind: c_int = try!(fullinfo(code, extra, detail::PCRE_INFO_MAXLOOKBEHIND, 0));
+    let match_empty: Option<c_int> = try!(optional_fullinfo(code, extra, detail::PCRE_INFO_MATCH_EMPTY, 0));
+    let has_cr_or_lf: c_int = try!(fullinfo(code, extra, detail::PCRE_INFO_HASCRORLF, 0));
+    let jit_size: size_t = try!(fullinfo(code, extra, detail::PCRE_INFO_JITSIZE, 0));
+    let ok_partial: c_int = try!(fullinfo(code, extra, detail::PCRE_INFO_OKPARTIAL, 0));
+    let match_limit: Option<u32> = try!(optional_fullinfo(code, extra, detail::PCRE_INFO_MATCHLIMIT, 0));
+    let recursion_limit: Option<u32> = try!(optional_fullinfo(code, extra, detail::PCRE_INFO_RECURSIONLIMIT, 0));
+
+    let compile_options = compile_options_from_bits(options);
+    Ok(PatternInfo {
+        anchored: compile_options.contains_elem(Anchored),
+        options: compile_options,
+        size: size as uint,
+        study_size: study_size as uint,
+        capture_count: capture_count as uint,
+        backref_max: backref_max as uint,
+        first_character: if first_character_flags == 1 { Some(first_character) } else { None },
+        starts_at_newline: first_character_flags == 2,
+        first_table: if first_table.is_null() {
+            None
+        } else {
+            Some(vec::raw::from_buf_raw(first_table, 32u))
+        },
+        required_character: if required_character_flags != 0 { Some(required_character) } else { None },
+        // "If the pattern was not studied, or if no minimum length is known, -1 is returned"
+        min_length: if min_length < 0 { None } else { Some(min_length as uint) },
+        max_lookbehind: max_lookbehind as uint,
+        can_match_empty: match_empty.map(|match_empty| match_empty != 0),
+        has_cr_or_lf: has_cr_or_lf != 0,
+        jit_size: jit_size as uint,
+        ok_partial: ok_partial != 0,
+        match_limit: match_limit.map(|limit| limit as uint),
+        match_limit_recursion: recursion_limit.map(|limit| limit as uint)
+    })
+}
+
+/// The maximum number of regular expressions combined into one by a `PcreSet`, limited by
+/// the callout numbers 0 to 255.
+static MAX_SET_CHUNK_SIZE: uint = 128u;
+
+/// The compile options which can be set within a group, as `(?imsxXUJ:...)`.
+static INLINE_OPTION_BITS: c_ulong = (Caseless as c_ulong) | (Multiline as c_ulong) | (DotAll as c_ulong)
+    | (Extended as c_ulong) | (Extra as c_ulong) | (Ungreedy as c_ulong) | (DupNames as c_ulong);
+
+/// Converts a set of compile options into the bits passed to `pcre_compile()`.
+fn compile_option_bits(options: &EnumSet<CompileOption>) -> c_ulong {
+    options.iter().fold(0, |bits, option| bits | option as c_ulong)
+}
+
+/// Returns the option letters for the inline compile options in `bits`.
+fn inline_option_letters(bits: c_ulong) -> ~str {
+    let letters = [(Caseless, 'i'), (Multiline, 'm'), (DotAll, 's'), (Extended, 'x'), (Extra, 'X'), (Ungreedy, 'U'), (DupNames, 'J')];
+    let mut s = ~"";
+    for &(option, letter) in letters.iter() {
+        if (bits & option as c_ulong) != 0 {
+            s.push_char(letter);
+        }
+    }
+    s
+}
+
+/// Returns `true` if `pattern` might have an option setting such as `(?x)` or `(?ix:`
+/// which turns on the `Extended` option. Any `x` among the letters after a `(?` counts.
+fn sets_extended_inline(pattern: &str) -> bool {
+    let mut rest = pattern;
+    loop {
+        match rest.find_str("(?") {
+            None => return false,
+            Some(i) => {
+                rest = rest.slice_from(i + 2);
+                let end = rest.find(|c: char| !(c.is_alphabetic() || c == '-')).unwrap_or(rest.len());
+                if rest.slice_to(end).contains_char('x') {
+                    return true;
+                }
             }
         }
     }
 }
 
-#[unsafe_destructor]
-impl<'a> Drop for MatchIterator<'a> {
-    fn drop(&mut self) {
-        unsafe {
-            if detail::pcre_refcount(self.code as *mut detail::pcre, -1) == 0 {
-                detail::pcre_free_study(self.extra as *mut detail::pcre_extra);
-                detail::pcre_free(self.code as *mut detail::pcre as *mut c_void);
+/// Returns `true` if the regular expression `re`, compiled from `pattern`, still matches
+/// the same strings when it is an alternative of a combined regular expression.
+fn can_combine(pattern: &str, options: &EnumSet<CompileOption>, re: &Pcre) -> bool {
+    // Backreferences, subroutine calls and conditions refer to groups by number, which
+    // changes once the pattern is combined, and names may clash with those of other
+    // patterns.
+    let backref_max: c_int = unsafe {
+        fullinfo(re.code.ptr, ptr::null(), detail::PCRE_INFO_BACKREFMAX, 0).unwrap()
+    };
+    if backref_max != 0 || re.name_count() != 0 {
+        return false;
+    }
+    // Automatic and explicit callouts would be mistaken for those of the set.
+    if options.contains_elem(AutoCallout) {
+        return false;
+    }
+    // A comment would swallow the end of the group.
+    if pattern.contains_char('#') && (options.contains_elem(Extended) || sets_extended_inline(pattern)) {
+        return false;
+    }
+    // Items such as (*UTF8) are only recognized at the start of a pattern, and backtracking
+    // control verbs such as (*COMMIT) or (*ACCEPT) anywhere else affect the other
+    // alternatives or skip the end callout. \K would move the start of the match reported by
+    // the callouts.
+    if pattern.contains("(*") || pattern.contains("\\K") || pattern.contains("\\g") {
+        return false;
+    }
+    let bytes = pattern.as_bytes();
+    for i in range(0u, bytes.len()) {
+        if !bytes.slice_from(i).starts_with(bytes!("(?")) || i + 2 >= bytes.len() {
+            continue;
+        }
+        let rest = bytes.slice_from(i + 2);
+        let digit_at = |j: uint| j < rest.len() && (rest[j] as char).is_digit();
+        if digit_at(0) || ((rest[0] == '+' as u8 || rest[0] == '-' as u8) && digit_at(1))
+            || "RC&(".contains_char(rest[0] as char) || rest.starts_with(bytes!("P>")) {
+            return false;
+        }
+    }
+    true
+}
+
+/// Compiles the alternatives `chunk` into one regular expression with the options `outer`.
+/// If the combined regular expression fails to compile, for example because it is too
+/// large, the alternatives are matched one at a time instead.
+fn combine(outer: c_ulong, chunk: ~[(uint, ~str, Pcre)], chunks: &mut ~[SetChunk], separate: &mut ~[(uint, Pcre)]) {
+    if chunk.len() < 2 {
+        for (index, _, re) in chunk.move_iter() {
+            separate.push((index, re));
+        }
+        return;
+    }
+
+    let mut pattern = ~"";
+    for (k, &(_, ref alternative, _)) in chunk.iter().enumerate() {
+        if k > 0 {
+            pattern.push_char('|');
+        }
+        pattern.push_str(format!("(?C{:u}){:s}(?C{:u})", 2 * k, *alternative, 2 * k + 1));
+    }
+    match Pcre::compile_with_options(pattern, &compile_options_from_bits(outer)) {
+        Ok(mut re) => {
+            re.study();
+            chunks.push(SetChunk {
+                re: re,
+                indices: chunk.iter().map(|&(index, _, _)| index).collect()
+            });
+        },
+        Err(_) => {
+            for (index, _, re) in chunk.move_iter() {
+                separate.push((index, re));
             }
-            self.extra = ptr::null();
-            self.code = ptr::null();
         }
     }
 }
 
-impl<'a> Iterator<Match<'a>> for MatchIterator<'a> {
-    /// Gets the next match.
-    #[inline]
-    fn next(&mut self) -> Option<Match<'a>> {
-        unsafe {
-            // Create a new, non-owning copy of `self.subject_cstring` to avoid
-            // error: closure requires unique access to `self` but `self.subject_cstring` is already borrowed
-            let subject_cstring_copy = self.subject_cstring.with_ref(|subject_c_str| CString::new(subject_c_str, false));
-            subject_cstring_copy.with_ref(|subject_c_str| -> Option<Match<'a>> {
-                // if self.extra.is_not_null() && (*self.extra).mark.is_not_null() {
-                //     (*self.extra).mark = &mut self.mark as *mut *mut u8;
-                // }
-                let rc = detail::pcre_exec(self.code, self.extra, subject_c_str, self.subject.len() as c_int, self.offset, &self.options, self.ovector.as_mut_ptr(), self.ovector.len() as c_int);
-                if rc >= 0 {
-                    // Update the iterator state.
-                    self.offset = self.ovector[1];
+/// The bits of the compile and exec options which select the newline convention.
+static NEWLINE_MASK: c_ulong = 0x00700000;
 
-                    // TODO add Mark to this as well
+/// Returns `true` if CRLF is a newline when matching the regular expression `code` with
+/// the options `options`. The newline convention is taken from the options, then from the
+/// regular expression, and then from libpcre's build configuration.
+fn crlf_is_newline(code: *detail::pcre, options: &EnumSet<ExecOption>) -> bool {
+    let exec_bits = options.iter().fold(0, |bits, option| bits | option as c_ulong) & NEWLINE_MASK;
+    let bits = if exec_bits != 0 {
+        exec_bits
+    } else {
+        let compile_bits: c_ulong = unsafe {
+            fullinfo(code, ptr::null(), detail::PCRE_INFO_OPTIONS, 0).unwrap()
+        };
+        compile_bits & NEWLINE_MASK
+    };
+    if bits == 0 {
+        // 0x0d0a for CRLF, -1 for ANY and -2 for ANYCRLF.
+        let newline: c_int = unsafe { config(detail::PCRE_CONFIG_NEWLINE, 0).unwrap() };
+        return newline == 0x0d0a || newline == -1 || newline == -2;
+    }
+    bits == NewlineCRLF as c_ulong || bits == NewlineAny as c_ulong || bits == NewlineAnyCRLF as c_ulong
+}
+
+/// Converts the options returned by `pcre_fullinfo()` into a set of compile options.
+/// Options without a `CompileOption`, such as PCRE_UTF8, are left out.
+fn compile_options_from_bits(bits: c_ulong) -> EnumSet<CompileOption> {
+    // The newline and \R options are made of several bits.
+    static BSR_MASK: c_ulong = 0x01800000;
+    let mut options: EnumSet<CompileOption> = EnumSet::empty();
+    for n in range(1u, 22u) {
+        let option: CompileOption = CLike::from_uint(n);
+        let value = option as c_ulong;
+        let set = if (value & NEWLINE_MASK) != 0 {
+            (bits & NEWLINE_MASK) == value
+        } else if (value & BSR_MASK) != 0 {
+            (bits & BSR_MASK) == value
+        } else {
+            (bits & value) != 0
+        };
+        if set {
+            options.add(option);
+        }
+    }
+    options
+}
 
-                    // let mark = if self.mark.is_not_null() {
-                    //     Some(std::str::raw::from_c_str(self.mark as *i8))
-                    // } else {
-                    //     None
-                    // };
+/// The first bytes of a regular expression saved by `Pcre::save()`.
+static SAVE_MAGIC: &'static [u8] = bytes!("rust-pcre");
 
-                    Some(Match {
-                        subject: self.subject,
-                        partial_ovector: self.ovector.slice_to(((self.capture_count + 1) * 2) as uint).to_owned(),
-                        string_count_: rc,
-                        mark: None
-                    })
-                } else {
-                    None
+/// The version of the format written by `Pcre::save()`, which is, after `SAVE_MAGIC`:
+///
+/// * u32 format version
+/// * block libpcre version
+/// * u32 size of a pointer
+/// * u32 link size
+/// * u32 1 if compiled in UTF-8 mode, otherwise 0
+/// * u32 compile options
+/// * block pattern
+/// * block character tables, empty for the default tables
+/// * block compiled code, in the byte order of the saving machine
+/// * block study data, empty if not studied
+/// * u32 1 if studied, otherwise 0
+/// * u32 study options
+/// * u32 checksum of all of the preceding bytes
+///
+/// Each u32 is little-endian, and each block is a u32 length followed by that many bytes.
+static SAVE_FORMAT_VERSION: u32 = 2;
+
+fn push_u32(bytes: &mut ~[u8], value: u32) {
+    for i in range(0u32, 4) {
+        bytes.push((value >> (8 * i)) as u8);
+    }
+}
+
+fn push_block(bytes: &mut ~[u8], block: &[u8]) {
+    push_u32(bytes, block.len() as u32);
+    bytes.push_all(block);
+}
+
+/// The 32-bit FNV-1a hash of `bytes`.
+fn save_checksum(bytes: &[u8]) -> u32 {
+    let mut hash = 2166136261u32;
+    for &b in bytes.iter() {
+        hash = (hash ^ b as u32) * 16777619u32;
+    }
+    hash
+}
+
+/// Reads the fields of a regular expression saved by `Pcre::save()`.
+struct SaveReader<'a> {
+    bytes: &'a [u8],
+    pos: uint
+}
+
+impl<'a> SaveReader<'a> {
+    fn read_u32(&mut self) -> Result<u32, LoadError> {
+        if self.bytes.len() - self.pos < 4 {
+            return Err(MalformedSaveError("truncated"));
+        }
+        let mut value = 0u32;
+        for i in range(0u, 4) {
+            value |= self.bytes[self.pos + i] as u32 << (8 * i);
+        }
+        self.pos += 4;
+        Ok(value)
+    }
+
+    fn read_block(&mut self) -> Result<&'a [u8], LoadError> {
+        let len = try!(self.read_u32()) as uint;
+        if self.bytes.len() - self.pos < len {
+            return Err(MalformedSaveError("truncated"));
+        }
+        let bytes = self.bytes;
+        self.pos += len;
+        Ok(bytes.slice(self.pos - len, self.pos))
+    }
+}
+
+/// The fields of a regular expression saved by `Pcre::save()`.
+struct SavedPcre<'a> {
+    version: &'a [u8],
+    pointer_size: u32,
+    link_size: u32,
+    utf8: bool,
+    options: EnumSet<CompileOption>,
+    pattern: &'a [u8],
+    tables: Option<Arc<CharTables>>,
+    code: &'a [u8],
+    study_data: &'a [u8],
+    study_options: Option<EnumSet<StudyOption>>
+}
+
+/// Reads and checks the fields of a regular expression saved by `Pcre::save()`.
+fn read_saved<'a>(bytes: &'a [u8]) -> Result<SavedPcre<'a>, LoadError> {
+    if bytes.len() < SAVE_MAGIC.len() + 4 || !bytes.starts_with(SAVE_MAGIC) {
+        return Err(MalformedSaveError("not a saved regular expression"));
+    }
+    let contents = bytes.slice_to(bytes.len() - 4);
+    let checksum = bytes.slice_from(bytes.len() - 4);
+    let mut reader = SaveReader { bytes: checksum, pos: 0 };
+    if try!(reader.read_u32()) != save_checksum(contents) {
+        return Err(MalformedSaveError("checksum mismatch"));
+    }
+
+    let mut reader = SaveReader { bytes: contents, pos: SAVE_MAGIC.len() };
+    if try!(reader.read_u32()) != SAVE_FORMAT_VERSION {
+        return Err(MalformedSaveError("unsupported format version"));
+    }
+    let version = try!(reader.read_block());
+    let pointer_size = try!(reader.read_u32());
+    let link_size = try!(reader.read_u32());
+    let utf8 = try!(reader.read_u32()) != 0;
+    let options = compile_options_from_bits(try!(reader.read_u32()) as c_ulong);
+    let pattern = try!(reader.read_block());
+    let tables = try!(reader.read_block());
+    let code = try!(reader.read_block());
+    let study_data = try!(reader.read_block());
+    let studied = try!(reader.read_u32()) != 0;
+    let study_bits = try!(reader.read_u32());
+    if reader.pos != contents.len() {
+        return Err(MalformedSaveError("trailing bytes"));
+    }
+    if tables.len() != 0 && tables.len() != detail::PCRE_TABLES_LENGTH {
+        return Err(MalformedSaveError("character tables have the wrong length"));
+    }
+
+    let study_options = if studied {
+        let mut study_options: EnumSet<StudyOption> = EnumSet::empty();
+        for &option in [StudyJitCompile, StudyJitPartialSoftCompile, StudyJitPartialHardCompile, StudyExtraNeeded].iter() {
+            if (study_bits & option as u32) != 0 {
+                study_options.add(option);
+            }
+        }
+        Some(study_options)
+    } else {
+        None
+    };
+
+    Ok(SavedPcre {
+        version: version,
+        pointer_size: pointer_size,
+        link_size: link_size,
+        utf8: utf8,
+        options: options,
+        pattern: pattern,
+        tables: if tables.is_empty() { None } else { Some(Arc::new(CharTables::from_bytes(tables))) },
+        code: code,
+        study_data: study_data,
+        study_options: study_options
+    })
+}
+
+impl<'a> SavedPcre<'a> {
+    /// Compiles the saved pattern and studies it with the saved options, if it was studied.
+    fn recompile(&self) -> Result<Pcre, LoadError> {
+        let mut re = match Pcre::compile_internal(self.pattern, &self.options, self.utf8, self.tables.clone()) {
+            Err(err) => return Err(RecompileError(err)),
+            Ok(re) => re
+        };
+        match self.study_options {
+            None => (),
+            Some(ref study_options) => match re.study_internal(study_options) {
+                Err(err) => return Err(RestudyError(err)),
+                Ok(_) => ()
+            }
+        }
+        Ok(re)
+    }
+}
+
+/// Copies saved compiled code and study data into blocks allocated by `pcre_malloc()` and
+/// converts them to the byte order of this machine.
+///
+/// # Return value
+/// The regular expression, or `None` if libpcre rejects the compiled code.
+unsafe fn load_code(saved: &SavedPcre) -> Option<Pcre> {
+    let code = saved.code;
+    let study_data = saved.study_data;
+    // libpcre trusts the size in the header of the compiled code, which is the second
+    // 32-bit field in either byte order.
+    if code.len() < 8 {
+        return None;
+    }
+    let size = code.slice(4, 8);
+    let size_le = size[0] as uint | size[1] as uint << 8 | size[2] as uint << 16 | size[3] as uint << 24;
+    let size_be = size[3] as uint | size[2] as uint << 8 | size[1] as uint << 16 | size[0] as uint << 24;
+    if size_le != code.len() && size_be != code.len() {
+        return None;
+    }
+
+    let codeptr = detail::pcre_malloc(code.len()) as *mut u8;
+    assert!(codeptr.is_not_null());
+    ptr::copy_nonoverlapping_memory(codeptr, code.as_ptr(), code.len());
+
+    // pcre_study() allocates the extra block and the study data together, which is what
+    // pcre_free_study() expects.
+    let mut extra: *mut detail::pcre_extra = ptr::mut_null();
+    if !study_data.is_empty() {
+        let extra_size = mem::size_of::<detail::pcre_extra>();
+        extra = detail::pcre_malloc(extra_size + study_data.len()) as *mut detail::pcre_extra;
+        assert!(extra.is_not_null());
+        *extra = detail::pcre_extra::empty();
+        (*extra).flags = detail::PCRE_EXTRA_STUDY_DATA;
+        (*extra).study_data = (extra as *mut u8).offset(extra_size as int) as *mut c_void;
+        ptr::copy_nonoverlapping_memory((*extra).study_data as *mut u8, study_data.as_ptr(), study_data.len());
+    }
+
+    let tableptr: *c_uchar = match saved.tables {
+        None => ptr::null(),
+        Some(ref tables) => tables.tables
+    };
+    let converted = detail::pcre_pattern_to_host_byte_order(codeptr as *mut detail::pcre, extra, tableptr).is_ok() && {
+        let size: Result<size_t, c_int> = fullinfo(codeptr as *detail::pcre, extra as *detail::pcre_extra, detail::PCRE_INFO_SIZE, 0);
+        let study_size: Result<size_t, c_int> = fullinfo(codeptr as *detail::pcre, extra as *detail::pcre_extra, detail::PCRE_INFO_STUDYSIZE, 0);
+        size == Ok(code.len() as size_t) && study_size == Ok(study_data.len() as size_t)
+    };
+    if !converted {
+        detail::pcre_free_study(extra);
+        detail::pcre_free(codeptr as *mut c_void);
+        return None;
+    }
+    Some(Pcre::from_code(codeptr as *detail::pcre, extra, saved.study_options.clone(), saved.pattern.to_owned(), saved.options.clone(), saved.utf8, saved.tables.clone()))
+}
+
+/// Reads the name-to-number translation table of the compiled regular expression `code`.
+/// libpcre keeps the table in name order, so the entries are sorted by name, and the
+/// groups sharing a name with PCRE_DUPNAMES are next to each other.
+unsafe fn read_name_table(code: *detail::pcre) -> ~[(~str, ~[uint])] {
+    let name_count: c_int = fullinfo(code, ptr::null(), detail::PCRE_INFO_NAMECOUNT, 0).unwrap();
+    let mut tabptr: *c_uchar = fullinfo(code, ptr::null(), detail::PCRE_INFO_NAMETABLE, ptr::null()).unwrap();
+    let name_entry_size: c_int = fullinfo(code, ptr::null(), detail::PCRE_INFO_NAMEENTRYSIZE, 0).unwrap();
+
+    let mut name_table: ~[(~str, ~[uint])] = ~[];
+
+    let mut i = 0;
+    while i < name_count {
+        let n: uint = (ptr::read(tabptr) as uint << 8) | (ptr::read(tabptr.offset(1)) as uint);
+        let name_cstring = c_str::CString::new(tabptr.offset(2) as *c_char, false);
+        let name = name_cstring.as_str().unwrap();
+        let same_name = match name_table.last() {
+            Some(&(ref last_name, _)) => last_name.as_slice() == name,
+            None => false
+        };
+        if same_name {
+            let last = name_table.len() - 1;
+            let (_, ref mut n_vec) = name_table[last];
+            n_vec.push(n);
+        } else {
+            name_table.push((name.to_owned(), ~[n]));
+        }
+        tabptr = tabptr.offset(name_entry_size as int);
+        i += 1;
+    }
+
+    name_table
+}
+
+/// Returns the group numbers assigned to `name` in the sorted `name_table`.
+fn find_name<'a>(name_table: &'a [(~str, ~[uint])], name: &str) -> Option<&'a [uint]> {
+    name_table.bsearch(|&(ref entry_name, _)| entry_name.as_slice().cmp(&name)).map(|i| {
+        let (_, ref n_vec) = name_table[i];
+        n_vec.as_slice()
+    })
+}
+
+impl<'a> SplitIterator<'a> {
+    /// Returns the error which ended the iteration, or `None` if no match attempt failed.
+    pub fn error(&self) -> Option<ExecError> {
+        self.matches.error()
+    }
+
+    /// Finds the next field and any captured separators, appending them to `self.queue`.
+    ///
+    /// # Return value
+    /// `false` if the subject string has been exhausted. `true` otherwise.
+    fn fill_queue(&mut self) -> bool {
+        if self.finished {
+            return false;
+        }
+
+        loop {
+            if self.limit != 0 && self.field_count + 1 >= self.limit {
+                break;
+            }
+            let m = match self.matches.next() {
+                None => {
+                    if self.matches.error().is_some() {
+                        // Don't return the remainder of the subject as a field.
+                        self.finished = true;
+                        return false;
+                    }
+                    break;
+                },
+                Some(m) => m
+            };
+
+            let start = m.group_start(0u);
+            let end = m.group_end(0u);
+            if start == end && (end == 0 || end == self.subject.len()) {
+                // Empty matches at either end of the subject do not separate anything.
+                continue;
+            }
+
+            self.queue.push(self.subject.slice(self.last_end, start));
+            self.field_count += 1;
+            for group in m.captures().skip(1) {
+                self.queue.push(group.unwrap_or(""));
+            }
+            self.last_end = end;
+            return true;
+        }
+
+        self.queue.push(self.subject.slice_from(self.last_end));
+        self.field_count += 1;
+        self.finished = true;
+        true
+    }
+}
+
+impl<'a> Iterator<&'a str> for SplitIterator<'a> {
+    /// Gets the next field or captured separator.
+    fn next(&mut self) -> Option<&'a str> {
+        loop {
+            match self.queue.shift() {
+                None => {
+                    if !self.fill_queue() {
+                        // Any held back empty fields were trailing ones.
+                        return None;
+                    }
+                },
+                Some(item) => {
+                    if self.limit != 0 {
+                        return Some(item);
+                    } else if item.is_empty() {
+                        self.pending_empty += 1;
+                    } else if self.pending_empty > 0 {
+                        // The held back empty fields were not trailing after all.
+                        self.pending_empty -= 1;
+                        self.queue.unshift(item);
+                        return Some(self.subject.slice(0u, 0u));
+                    } else {
+                        return Some(item);
+                    }
                 }
-            })
+            }
         }
     }
 }
@@ -748,3 +4538,68 @@ impl<'a> Iterator<Match<'a>> for MatchIterator<'a> {
 pub fn pcre_version() -> ~str {
     detail::pcre_version()
 }
+
+/// Returns the features and defaults that libpcre was built with.
+pub fn build_config() -> BuildConfig {
+    unsafe {
+        let utf8: c_int = config(detail::PCRE_CONFIG_UTF8, 0).unwrap();
+        let utf16: Option<c_int> = optional_config(detail::PCRE_CONFIG_UTF16, 0);
+        let utf32: Option<c_int> = optional_config(detail::PCRE_CONFIG_UTF32, 0);
+        let unicode_properties: c_int = config(detail::PCRE_CONFIG_UNICODE_PROPERTIES, 0).unwrap();
+        let jit: c_int = config(detail::PCRE_CONFIG_JIT, 0).unwrap();
+        let jit_target: *c_char = config(detail::PCRE_CONFIG_JITTARGET, ptr::null()).unwrap();
+        let newline: c_int = config(detail::PCRE_CONFIG_NEWLINE, 0).unwrap();
+        let bsr: c_int = config(detail::PCRE_CONFIG_BSR, 0).unwrap();
+        let link_size: c_int = config(detail::PCRE_CONFIG_LINK_SIZE, 0).unwrap();
+        let parens_limit: Option<c_ulong> = optional_config(detail::PCRE_CONFIG_PARENS_LIMIT, 0);
+        let match_limit: c_ulong = config(detail::PCRE_CONFIG_MATCH_LIMIT, 0).unwrap();
+        let match_limit_recursion: c_ulong = config(detail::PCRE_CONFIG_MATCH_LIMIT_RECURSION, 0).unwrap();
+        let stack_recursion: c_int = config(detail::PCRE_CONFIG_STACKRECURSE, 0).unwrap();
+
+        BuildConfig {
+            utf8: utf8 != 0,
+            utf16: utf16.map(|utf16| utf16 != 0),
+            utf32: utf32.map(|utf32| utf32 != 0),
+            unicode_properties: unicode_properties != 0,
+            jit: jit != 0,
+            // "If JIT support is not available, NULL is returned."
+            jit_target: if jit_target.is_null() {
+                None
+            } else {
+                Some(str::raw::from_c_str(jit_target))
+            },
+            // The newline value is the character code of CR or LF, 0x0d0a for CRLF, -1 for
+            // ANY, or -2 for ANYCRLF. EBCDIC builds use other codes for LF.
+            newline: match newline {
+                -1 => NewlineAny,
+                -2 => NewlineAnyCRLF,
+                0x0d0a => NewlineCRLF,
+                0x0d => NewlineCR,
+                _ => NewlineLF
+            },
+            // "The output is an integer whose value is 0 if \R matches any Unicode line ending
+            // sequence, or 1 if it matches only CR, LF, or CRLF."
+            bsr: if bsr == 0 { BsrUnicode } else { BsrAnyCRLF },
+            link_size: link_size as uint,
+            parens_nest_limit: parens_limit.map(|limit| limit as uint),
+            match_limit: match_limit as uint,
+            match_limit_recursion: match_limit_recursion as uint,
+            stack_recursion: stack_recursion != 0
+        }
+    }
+}
+
+/// Queries the build configuration field `what`, whose value has type `T`.
+unsafe fn config<T>(what: detail::config_field, initial: T) -> Result<T, c_int> {
+    let mut value = initial;
+    match detail::pcre_config(what, &mut value as *mut T as *mut c_void) {
+        Ok(()) => Ok(value),
+        Err(rc) => Err(rc)
+    }
+}
+
+/// Queries a build configuration field which is not supported by every libpcre version
+/// or library width, returning `None` if it is not supported.
+unsafe fn optional_config<T>(what: detail::config_field, initial: T) -> Option<T> {
+    config(what, initial).ok()
+}
@@ -1,8 +1,11 @@
 extern crate pcre;
 extern crate collections;
+extern crate sync;
 
 use collections::EnumSet;
-use pcre::{CompileOption, StudyOption, ExtraOption, Pcre};
+use pcre::{CompileOption, ExecOption, ExtraOption, JitStack, Match, MatchLimits, PatternCache, Pcre, PcreBuilder, PcreSet, StudyOption};
+use std::vec;
+use sync::{Arc, Future};
 
 #[test]
 #[should_fail]
@@ -31,35 +34,35 @@ fn test_compile_capture_count() {
 
 #[test]
 fn test_exec_basic() {
-    let mut re = Pcre::compile("^...$").unwrap();
+    let re = Pcre::compile("^...$").unwrap();
     assert_eq!(re.capture_count(), 0u);
-    let m = re.exec("abc").unwrap();
+    let m = re.exec("abc").unwrap().unwrap();
     assert_eq!(m.group(0), "abc");
 }
 
 #[test]
 fn test_exec_no_match() {
-    let mut re = Pcre::compile("abc").unwrap();
-    assert!(re.exec("def").is_none());
+    let re = Pcre::compile("abc").unwrap();
+    assert!(re.exec("def").unwrap().is_none());
 }
 
 #[test]
 fn test_exec_nul_byte() {
     // Nul bytes *are* allowed in subject strings, however.
-    let mut re = Pcre::compile("abc\\0def").unwrap();
-    let m = re.exec("abc\0def").unwrap();
+    let re = Pcre::compile("abc\\0def").unwrap();
+    let m = re.exec("abc\0def").unwrap().unwrap();
     assert_eq!(m.group(0), "abc\0def");
 }
 
 #[test]
 fn test_exec_from_basic() {
-    let mut re = Pcre::compile("abc").unwrap();
+    let re = Pcre::compile("abc").unwrap();
     let subject = "abcabc";
-    let m1 = re.exec_from(subject, 1u).unwrap();
+    let m1 = re.exec_from(subject, 1u).unwrap().unwrap();
     assert_eq!(m1.group_start(0u), 3u);
     assert_eq!(m1.group_end(0u), 6u);
     assert_eq!(m1.group_len(0u), 3u);
-    let m2 = re.exec(subject).unwrap();
+    let m2 = re.exec(subject).unwrap().unwrap();
     assert_eq!(m2.group_start(0u), 0u);
 }
 
@@ -128,43 +131,28 @@ fn test_extra_mark() {
 
     let mut re = Pcre::compile_with_options(pattern, &compile_options).unwrap();
 
-    // first try to get the mark from the compile to make sure it fails
-    assert_eq!(re.get_mark(), None);
-
     let mut study_options: EnumSet<StudyOption> = EnumSet::empty();
     study_options.add(pcre::StudyJitCompile);
     let study = re.study_with_options(&study_options);
     // Double check to make sure the study worked
     assert!(study);
 
-    // Now after studying, we still should not be able to get the mark (since we still need 
-    // to set the option in the extra AND execute it)
-    assert_eq!(re.get_mark(), None);
-
     // set that I am using the extra mark field
     let mut extra_options: EnumSet<ExtraOption> = EnumSet::empty();
     extra_options.add(pcre::ExtraMark);
-    let extra = re.set_extra_options(&extra_options);
-    // This will fail only if I didn't study first
-    assert!(extra);
+    assert!(re.set_extra_options(&extra_options));
 
-    // We still haven't run the pcre_exec yet so get mark should be None still
-    assert_eq!(re.get_mark(), None);
-
-    // Now execute and we should be able to get the mark
-    let opt_m1 = re.exec(subject1);
+    let opt_m1 = re.exec(subject1).unwrap();
     assert!(opt_m1.is_some());
 
-    // It should match XY 
+    // It should match XY
     let m1 = opt_m1.unwrap();
     assert_eq!(m1.group(0), "XY");
 
     // and the marked value should be A
-    let mark1 = re.get_mark();
-    assert!(mark1.is_some());
-    assert_eq!(mark1.unwrap(), ~"A");
+    assert_eq!(m1.mark(), Some("A"));
 
-    let opt_m2 = re.exec(subject2);
+    let opt_m2 = re.exec(subject2).unwrap();
     assert!(opt_m2.is_some());
 
     let m2 = opt_m2.unwrap();
@@ -172,5 +160,859 @@ fn test_extra_mark() {
     assert_eq!(m2.group(0), "XZ");
 
     // and the marked value should be B
-    assert_eq!(re.get_mark().unwrap(), ~"B");
-}
\ No newline at end of file
+    assert_eq!(m2.mark(), Some("B"));
+    // The first match keeps its own mark.
+    assert_eq!(m1.mark(), Some("A"));
+}
+
+#[test]
+fn test_replace_template() {
+    let re = Pcre::compile("(\\w+)@(?<host>\\w+)").unwrap();
+    let subject = "mail a@b, c@d";
+    assert_eq!(re.replace(subject, "${host}:$1").unwrap().as_slice(), "mail b:a, c@d");
+    assert_eq!(re.replace_all(subject, "${host}:${1} $$").unwrap().as_slice(), "mail b:a $, d:c $");
+    assert_eq!(re.replace_n(subject, 2u, "<$0>").unwrap().as_slice(), "mail <a@b>, <c@d>");
+    // References to groups that do not exist are replaced by the empty string.
+    assert_eq!(re.replace(subject, "[$7${nope}]").unwrap().as_slice(), "mail [], c@d");
+}
+
+#[test]
+fn test_replace_closure() {
+    let re = Pcre::compile("\\d+").unwrap();
+    let replaced = re.replace_all("1 22 333", |m: &Match| -> ~str {
+        m.group_len(0u).to_str()
+    }).unwrap();
+    assert_eq!(replaced.as_slice(), "1 2 3");
+}
+
+#[test]
+fn test_replace_no_match() {
+    let re = Pcre::compile("xyz").unwrap();
+    let replaced = re.replace_all("abc", "$0$0").unwrap();
+    assert!(replaced.is_slice());
+    assert_eq!(replaced.as_slice(), "abc");
+}
+
+#[test]
+fn test_split_basic() {
+    let re = Pcre::compile("\\s*,\\s*").unwrap();
+    assert_eq!(re.split("a , b,c"), Ok(~["a", "b", "c"]));
+    // Leading empty fields are kept but trailing empty fields are dropped.
+    assert_eq!(re.split(",a,,b,,"), Ok(~["", "a", "", "b"]));
+    assert_eq!(re.split(",,,"), Ok(~[]));
+    assert_eq!(re.split(""), Ok(~[]));
+}
+
+#[test]
+fn test_split_captured_separators() {
+    let re = Pcre::compile("(-)|(\\+)").unwrap();
+    assert_eq!(re.split("1-2+3"), Ok(~["1", "-", "", "2", "", "+", "3"]));
+}
+
+#[test]
+fn test_splitn() {
+    let re = Pcre::compile(":").unwrap();
+    assert_eq!(re.splitn("a:b:c:d", 2u), Ok(~["a", "b:c:d"]));
+    // Trailing empty fields are kept when a limit is given.
+    assert_eq!(re.splitn("a:b::", 4u), Ok(~["a", "b", "", ""]));
+    assert_eq!(re.splitn("a:b::", 0u), Ok(~["a", "b"]));
+}
+
+#[test]
+fn test_split_iter_lazy() {
+    let re = Pcre::compile(";").unwrap();
+    let mut it = re.split_iter("x;;y;");
+    assert_eq!(it.next(), Some("x"));
+    assert_eq!(it.next(), Some(""));
+    assert_eq!(it.next(), Some("y"));
+    assert_eq!(it.next(), None);
+}
+
+#[test]
+fn test_match_named_groups() {
+    let re = Pcre::compile("(?<year>\\d{4})-(?<month>\\d\\d)(?:-(?<day>\\d\\d))?").unwrap();
+    let m = re.exec("on 2014-03").unwrap().unwrap();
+    assert_eq!(m.group_by_name("year"), Some("2014"));
+    assert_eq!(m.name_start("month"), Some(8u));
+    assert_eq!(m.name_end("month"), Some(10u));
+    assert_eq!(m.group_by_name("day"), None);
+    assert_eq!(m.group_by_name("no_such_group"), None);
+
+    let groups: ~[(&str, Option<&str>)] = m.named_groups().collect();
+    assert_eq!(groups, ~[("day", None), ("month", Some("03")), ("year", Some("2014"))]);
+}
+
+#[test]
+fn test_match_named_groups_dupnames() {
+    let mut compile_options: EnumSet<CompileOption> = EnumSet::empty();
+    compile_options.add(pcre::DupNames);
+    let re = Pcre::compile_with_options("(?<n>a)|(?<n>b)", &compile_options).unwrap();
+    assert_eq!(re.name_table().find(&~"n"), Some(&~[1u, 2u]));
+    assert_eq!(re.name_count(), 2u);
+    let m = re.exec("b").unwrap().unwrap();
+    assert_eq!(m.group_by_name("n"), Some("b"));
+    assert_eq!(m.name_start("n"), Some(0u));
+}
+
+#[test]
+fn test_match_unset_groups() {
+    let re = Pcre::compile("(a)|(b)").unwrap();
+    let m = re.exec("b").unwrap().unwrap();
+    assert_eq!(m.get(0u), Some("b"));
+    assert_eq!(m.get(1u), None);
+    assert_eq!(m.range(1u), None);
+    assert_eq!(m.get(2u), Some("b"));
+    assert_eq!(m.range(2u), Some((0u, 1u)));
+    // Groups beyond the capture count are never set.
+    assert_eq!(m.get(3u), None);
+
+    let captures: ~[Option<&str>] = m.captures().collect();
+    assert_eq!(captures, ~[Some("b"), None, Some("b")]);
+}
+
+#[test]
+#[should_fail]
+fn test_match_group_unset_fails() {
+    let re = Pcre::compile("(a)|(b)").unwrap();
+    let m = re.exec("b").unwrap().unwrap();
+    drop(m.group(1u));
+}
+
+#[test]
+fn test_exec_bytes_non_utf8() {
+    // In non-UTF-8 mode, `.` matches any single byte.
+    let re = Pcre::compile_bytes(bytes!("a(.)c")).unwrap();
+    let subject = bytes!("xa", 0xff, "c");
+    let m = re.exec_bytes(subject).unwrap().unwrap();
+    assert_eq!(m.group(0u), bytes!("a", 0xff, "c"));
+    assert_eq!(m.get(1u), Some(bytes!(0xff)));
+    assert_eq!(m.group_start(0u), 1u);
+}
+
+#[test]
+fn test_exec_bytes_validates_utf8() {
+    let re = Pcre::compile("b").unwrap();
+    assert!(re.exec_bytes(bytes!("ab")).unwrap().is_some());
+
+    match re.exec_bytes(bytes!("ab", 0xc3, 0x28, "b")) {
+        Err(pcre::BadUtf8Error(err)) => {
+            assert_eq!(err.code(), -10);
+            assert_eq!(err.offset(), 2u);
+            assert!(!err.is_truncated());
+        },
+        _ => fail!("expected BadUtf8Error")
+    }
+
+    match re.exec_bytes(bytes!("ab", 0xc3)) {
+        Err(pcre::BadUtf8Error(err)) => {
+            assert!(err.is_truncated());
+            assert_eq!(err.reason(), 1);
+            assert_eq!(err.offset(), 2u);
+        },
+        _ => fail!("expected BadUtf8Error")
+    }
+}
+
+#[test]
+fn test_exec_match_limit_error() {
+    // Catastrophic backtracking hits the default match limit instead of failing the task.
+    let re = Pcre::compile("(a+)+$").unwrap();
+    let subject = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaab";
+    let err = re.exec(subject).unwrap_err();
+    assert_eq!(err, pcre::MatchLimitError);
+    assert_eq!(err.code(), -8);
+
+    let mut it = re.matches(subject);
+    assert!(it.next().is_none());
+    assert_eq!(it.error(), Some(pcre::MatchLimitError));
+    assert_eq!(re.split(subject), Err(pcre::MatchLimitError));
+}
+
+#[test]
+fn test_exec_bad_offset_error() {
+    let re = Pcre::compile("a").unwrap();
+    assert_eq!(re.exec_from("abc", 4u).unwrap_err(), pcre::BadOffsetError);
+}
+
+#[test]
+fn test_exec_bad_utf8_offset_error() {
+    let re = Pcre::compile(".").unwrap();
+    // Offset 1 is inside the two bytes of "é".
+    assert_eq!(re.exec_from("é", 1u).unwrap_err(), pcre::BadUtf8OffsetError);
+    let no_options: EnumSet<ExecOption> = EnumSet::empty();
+    let mut dfa = re.dfa_matcher();
+    assert_eq!(dfa.exec_from_with_options("éa", 1u, &no_options).unwrap_err(), pcre::BadUtf8OffsetError);
+    assert_eq!(re.exec_from("éa", 2u).unwrap().unwrap().group(0u), "a");
+}
+
+#[test]
+fn test_exec_non_utf8_pattern_error() {
+    let re = Pcre::compile_bytes(bytes!(0xc3)).unwrap();
+    // Without the check, the pattern would match the first byte of "é".
+    assert_eq!(re.exec("é").unwrap_err(), pcre::NonUtf8PatternError);
+    assert_eq!(re.is_match("é").unwrap_err(), pcre::NonUtf8PatternError);
+    assert_eq!(re.count_matches("é").unwrap_err(), pcre::NonUtf8PatternError);
+    assert_eq!(re.exec_bytes("é".as_bytes()).unwrap().unwrap().group(0u), bytes!(0xc3));
+}
+
+#[test]
+fn test_set_match_limit() {
+    let mut re = Pcre::compile("(a+)+b").unwrap();
+    let subject = "aaaaaaaaaaaaaaaaaaaaab";
+    assert!(re.exec(subject).unwrap().is_some());
+    assert_eq!(re.match_limit(), None);
+
+    // The limits apply without studying the pattern first.
+    re.set_match_limit(10u);
+    assert_eq!(re.match_limit(), Some(10u));
+    assert_eq!(re.exec("aaaaaaaaaaaaaaaaaaaaa").unwrap_err(), pcre::MatchLimitError);
+    let mut it = re.matches("aaaaaaaaaaaaaaaaaaaaa");
+    assert!(it.next().is_none());
+    assert_eq!(it.error(), Some(pcre::MatchLimitError));
+
+    re.set_match_limit_recursion(2u);
+    assert_eq!(re.match_limit_recursion(), Some(2u));
+}
+
+#[test]
+fn test_exec_from_with_limits() {
+    let re = Pcre::compile("(a+)+$").unwrap();
+    let no_options: EnumSet<ExecOption> = EnumSet::empty();
+    let limits = MatchLimits { match_limit: Some(1000u), match_limit_recursion: None };
+    let subject = "aaaaaaaaaaaaaaaaaaaab";
+    assert_eq!(re.exec_from_with_limits(subject, 0u, &no_options, &limits).unwrap_err(), pcre::MatchLimitError);
+
+    let limits = MatchLimits { match_limit: None, match_limit_recursion: Some(3u) };
+    assert_eq!(re.exec_from_with_limits(subject, 0u, &no_options, &limits).unwrap_err(), pcre::RecursionLimitError);
+
+    // The override applies to that call only.
+    assert!(re.exec_from_with_limits("aaa", 0u, &no_options, &MatchLimits::new()).unwrap().is_some());
+    assert_eq!(re.match_limit(), None);
+}
+
+#[test]
+fn test_exec_with_callout() {
+    let re = Pcre::compile("a(?C1)b(?C2)").unwrap();
+    let no_options: EnumSet<ExecOption> = EnumSet::empty();
+    let mut seen = ~[];
+    let m = re.exec_with_callout("xab", 0u, &no_options, |block| {
+        seen.push((block.callout_number(), block.start_match(), block.current_position()));
+        pcre::CalloutContinue
+    }).unwrap().unwrap();
+    assert_eq!(m.group(0u), "ab");
+    assert_eq!(seen, ~[(1u, 1u, 2u), (2u, 1u, 3u)]);
+}
+
+#[test]
+fn test_exec_with_callout_fail_and_abort() {
+    let re = Pcre::compile("\\w(?C1)").unwrap();
+    let no_options: EnumSet<ExecOption> = EnumSet::empty();
+    let m = re.exec_with_callout("abc", 0u, &no_options, |block| {
+        if block.current_position() < 3u { pcre::CalloutFail } else { pcre::CalloutContinue }
+    }).unwrap().unwrap();
+    assert_eq!(m.group(0u), "c");
+
+    let err = re.exec_with_callout("abc", 0u, &no_options, |_| pcre::CalloutAbort).unwrap_err();
+    assert_eq!(err, pcre::CalloutAbortedError);
+
+    // Without a callout function, callout points are ignored.
+    assert_eq!(re.exec("abc").unwrap().unwrap().group(0u), "a");
+}
+
+#[test]
+fn test_match_iterator_with_callout() {
+    let re = Pcre::compile("(\\d)(?C7)").unwrap();
+    let mut it = re.matches("a1b2");
+    let mut captured = ~[];
+    loop {
+        let opt_m = it.try_next_with_callout(|block| {
+            assert_eq!(block.callout_number(), 7u);
+            assert_eq!(block.capture_last(), Some(1u));
+            captured.push(block.group_range(1u).unwrap());
+            pcre::CalloutContinue
+        }).unwrap();
+        if opt_m.is_none() {
+            break;
+        }
+    }
+    assert_eq!(captured, ~[(1u, 2u), (3u, 4u)]);
+}
+
+#[test]
+fn test_exec_partial() {
+    let re = Pcre::compile("^\\d{4}-\\d{2}$").unwrap();
+    match re.exec_partial("2014-0").unwrap() {
+        pcre::Partial { start, end } => assert_eq!((start, end), (0u, 6u)),
+        _ => fail!("expected a partial match")
+    }
+    match re.exec_partial("2014-06").unwrap() {
+        pcre::Full(m) => assert_eq!(m.group(0u), "2014-06"),
+        _ => fail!("expected a complete match")
+    }
+    match re.exec_partial("2014-x").unwrap() {
+        pcre::NoMatch => (),
+        _ => fail!("expected no match")
+    }
+}
+
+#[test]
+fn test_exec_partial_hard() {
+    let re = Pcre::compile("dog(sbody)?").unwrap();
+    let mut options: EnumSet<ExecOption> = EnumSet::empty();
+    // A soft partial match prefers the complete match of "dog".
+    options.add(pcre::ExecPartialSoft);
+    match re.exec_partial_from_with_options("dogsb", 0u, &options).unwrap() {
+        pcre::Full(m) => assert_eq!(m.group(0u), "dog"),
+        _ => fail!("expected a complete match")
+    }
+    options = EnumSet::empty();
+    options.add(pcre::ExecPartialHard);
+    match re.exec_partial_from_with_options("dogsb", 0u, &options).unwrap() {
+        pcre::Partial { start, end } => assert_eq!((start, end), (0u, 5u)),
+        _ => fail!("expected a partial match")
+    }
+
+    // The plain exec family reports partial matches as an error.
+    let err = re.exec_from_with_options("dogsb", 0u, &options).unwrap_err();
+    assert_eq!(err, pcre::PartialMatchError);
+}
+
+#[test]
+fn test_dfa_matcher() {
+    let re = Pcre::compile("<.*>").unwrap();
+    let mut dfa = re.dfa_matcher();
+    match dfa.exec("x<a> <b>").unwrap() {
+        pcre::DfaFull(m) => {
+            assert_eq!(m.start(), 1u);
+            assert_eq!(m.count(), 2u);
+            assert_eq!(m.longest(), "<a> <b>");
+            assert_eq!(m.get(1u), Some("<a>"));
+            assert_eq!(m.get(2u), None);
+        },
+        _ => fail!("expected a match")
+    }
+
+    dfa.set_shortest(true);
+    match dfa.exec("x<a> <b>").unwrap() {
+        pcre::DfaFull(m) => {
+            assert_eq!(m.count(), 1u);
+            assert_eq!(m.longest(), "<a>");
+        },
+        _ => fail!("expected a match")
+    }
+
+    match dfa.exec("<a").unwrap() {
+        pcre::DfaNoMatch => (),
+        _ => fail!("expected no match")
+    }
+}
+
+#[test]
+fn test_dfa_matcher_restart() {
+    let re = Pcre::compile("abc\\d+").unwrap();
+    let mut dfa = re.dfa_matcher();
+    let mut options: EnumSet<ExecOption> = EnumSet::empty();
+    options.add(pcre::ExecPartialSoft);
+    match dfa.exec_from_with_options("xab", 0u, &options).unwrap() {
+        pcre::DfaPartial { start, end } => assert_eq!((start, end), (1u, 3u)),
+        _ => fail!("expected a partial match")
+    }
+    match dfa.restart("c12", &options).unwrap() {
+        pcre::DfaFull(m) => {
+            assert_eq!(m.count(), 2u);
+            assert_eq!(m.longest(), "c12");
+        },
+        _ => fail!("expected a match")
+    }
+
+    // A restart must follow a partial match.
+    let mut fresh = re.dfa_matcher();
+    let err = fresh.restart("c12", &options).unwrap_err();
+    assert_eq!(err, pcre::DfaBadRestartError);
+}
+
+#[test]
+fn test_jit_stack() {
+    let mut re = Pcre::compile("(?:(a)|b)*c").unwrap();
+    assert!(!re.is_jit_compiled());
+    assert_eq!(re.jit_size(), 0u);
+    assert!(!unsafe { re.assign_jit_stack(Arc::new(JitStack::new(32 * 1024, 1024 * 1024).unwrap())) });
+
+    let mut study_options: EnumSet<StudyOption> = EnumSet::empty();
+    study_options.add(pcre::StudyJitCompile);
+    re.study_with_options(&study_options);
+    if !re.is_jit_compiled() {
+        // libpcre was built without JIT support.
+        return;
+    }
+    assert!(re.jit_size() > 0u);
+
+    let subject = "ab".repeat(100000) + "c";
+    assert_eq!(re.exec(subject).unwrap_err(), pcre::JitStackLimitError);
+
+    assert!(unsafe { re.assign_jit_stack(Arc::new(JitStack::new(32 * 1024, 16 * 1024 * 1024).unwrap())) });
+    assert_eq!(re.exec(subject).unwrap().unwrap().group_len(0u), subject.len());
+
+    assert!(re.assign_task_jit_stack());
+    assert_eq!(re.exec(subject).unwrap_err(), pcre::JitStackLimitError);
+    assert!(JitStack::set_for_task(JitStack::new(32 * 1024, 16 * 1024 * 1024).unwrap()).is_none());
+    assert_eq!(re.exec(subject).unwrap().unwrap().group_len(0u), subject.len());
+}
+
+#[test]
+fn test_clone_across_tasks() {
+    let mut re = Pcre::compile("(\\w+)@(\\w+)\\.com").unwrap();
+    re.study();
+    let subject = "alice@example.com, bob@example.com";
+    let futures = vec::from_fn(8u, |_| {
+        let re = re.clone();
+        Future::spawn(proc() {
+            let mut count = 0u;
+            for _ in range(0u, 100u) {
+                let m = re.exec(subject).unwrap().unwrap();
+                assert_eq!(m.group(1u), "alice");
+                count += re.matches(subject).count();
+            }
+            count
+        })
+    });
+    for mut future in futures.move_iter() {
+        assert_eq!(future.get(), 200u);
+    }
+
+    // The original is unaffected by its clones being dropped.
+    assert_eq!(re.exec(subject).unwrap().unwrap().group(2u), "example");
+}
+
+#[test]
+fn test_pattern_info() {
+    let mut re = Pcre::compile("(?i)^(a)b+\\1\\r?$").unwrap();
+    let info = re.info().unwrap();
+    assert!(info.options.contains_elem(pcre::Caseless));
+    assert!(info.anchored);
+    assert!(info.size > 0u);
+    assert_eq!(info.study_size, 0u);
+    assert_eq!(info.capture_count, 1u);
+    assert_eq!(info.backref_max, 1u);
+    assert_eq!(info.min_length, None);
+    assert!(info.has_cr_or_lf);
+    assert!(info.ok_partial);
+    assert_eq!(info.match_limit, None);
+
+    re.study();
+    let info = re.info().unwrap();
+    assert_eq!(info.min_length, Some(3u));
+
+    let re = Pcre::compile("x?yz").unwrap();
+    let info = re.info().unwrap();
+    assert!(!info.anchored);
+    assert_eq!(info.first_character, None);
+    assert_eq!(info.required_character, Some('z' as u32));
+    assert_eq!(info.can_match_empty.unwrap_or(false), false);
+
+    let mut re = Pcre::compile("[ab]c|d").unwrap();
+    re.study();
+    let table = re.info().unwrap().first_table.unwrap();
+    assert_eq!(table.len(), 32u);
+    let is_first_byte = |b: char| (table[b as uint / 8] & (1u8 << (b as uint % 8))) != 0;
+    assert!(is_first_byte('a') && is_first_byte('b') && is_first_byte('d'));
+    assert!(!is_first_byte('c'));
+}
+
+#[test]
+fn test_build_config() {
+    let config = pcre::build_config();
+    // rust-pcre compiles patterns in UTF-8 mode.
+    assert!(config.utf8);
+    assert!(config.link_size >= 2u && config.link_size <= 4u);
+    assert!(config.match_limit > 0u);
+    assert_eq!(config.jit, config.jit_target.is_some());
+    match config.bsr {
+        pcre::BsrUnicode | pcre::BsrAnyCRLF => (),
+        _ => fail!("unexpected \\R convention")
+    }
+}
+
+#[test]
+fn test_char_tables() {
+    let no_options: EnumSet<CompileOption> = EnumSet::empty();
+    assert!(pcre::CharTables::for_locale("no_such_locale").is_none());
+
+    // In the C locale, 0xe4 (a-umlaut in ISO-8859-1) is not a word character.
+    let re = Pcre::compile_bytes_with_tables(bytes!("^\\w+$"), &no_options, Arc::new(pcre::CharTables::new())).unwrap();
+    assert!(re.exec_bytes(bytes!("b", 0xe4, "r")).unwrap().is_none());
+
+    let tables = match pcre::CharTables::for_locale("de_DE.ISO-8859-1") {
+        None => return, // The locale is not installed.
+        Some(tables) => Arc::new(tables)
+    };
+    let re = Pcre::compile_bytes_with_tables(bytes!("^\\w+$"), &no_options, tables.clone()).unwrap();
+    assert!(re.exec_bytes(bytes!("b", 0xe4, "r")).unwrap().is_some());
+
+    let mut caseless: EnumSet<CompileOption> = EnumSet::empty();
+    caseless.add(pcre::Caseless);
+    let re = Pcre::compile_bytes_with_tables(bytes!(0xc4), &caseless, tables).unwrap();
+    assert!(re.exec_bytes(bytes!(0xe4)).unwrap().is_some());
+}
+
+#[test]
+fn test_save_load() {
+    let mut re = Pcre::compile("(?<word>\\w+)@(\\w+)").unwrap();
+    re.study();
+    let saved = re.save();
+
+    let loaded = Pcre::load(saved).unwrap();
+    assert_eq!(loaded.capture_count(), 2u);
+    assert_eq!(loaded.info().unwrap().study_size, re.info().unwrap().study_size);
+    let m = loaded.exec("mail bob@example").unwrap().unwrap();
+    assert_eq!(m.group(1), "bob");
+    assert_eq!(m.group(2), "example");
+    assert!(loaded.name_table().contains_key(&~"word"));
+
+    let loaded = unsafe { Pcre::load_unchecked(saved).unwrap() };
+    assert_eq!(loaded.info().unwrap().study_size, re.info().unwrap().study_size);
+    assert_eq!(loaded.exec("mail bob@example").unwrap().unwrap().group(1), "bob");
+}
+
+#[test]
+fn test_save_load_study_options() {
+    let mut study_options: EnumSet<StudyOption> = EnumSet::empty();
+    study_options.add(pcre::StudyJitCompile);
+    let mut re = Pcre::compile("a+b").unwrap();
+    re.study_with_options(&study_options);
+
+    // The pattern is studied again with the saved options, so it is JIT-compiled again.
+    let loaded = Pcre::load(re.save()).unwrap();
+    assert_eq!(loaded.is_jit_compiled(), re.is_jit_compiled());
+    assert!(loaded.exec("aab").unwrap().is_some());
+}
+
+#[test]
+fn test_load_malformed() {
+    let saved = Pcre::compile("a+b").unwrap().save();
+    match Pcre::load(saved.slice_to(saved.len() - 1)) {
+        Err(pcre::MalformedSaveError(_)) => (),
+        _ => fail!("loaded a truncated regular expression")
+    }
+    let mut corrupted = saved.clone();
+    corrupted[saved.len() / 2] ^= 0xff;
+    match Pcre::load(corrupted) {
+        Err(pcre::MalformedSaveError(_)) => (),
+        _ => fail!("loaded a corrupted regular expression")
+    }
+    assert!(Pcre::load([]).is_err());
+}
+
+#[test]
+fn test_pcre_set() {
+    // (\w)\1 has a backreference, so it is matched on its own.
+    let set = PcreSet::new(["foo", "b(a|e)r", "^x", "(\\w)\\1", "\\Qa.b"]).unwrap();
+    assert_eq!(set.len(), 5u);
+    assert_eq!(set.matches("bar food").unwrap(), ~[0u, 1u, 3u]);
+    assert_eq!(set.matches("xa.b").unwrap(), ~[2u, 4u]);
+    assert_eq!(set.matches("nothing").unwrap(), ~[]);
+    assert!(set.is_match("beer").unwrap());
+    assert!(!set.is_match("bear").unwrap());
+
+    let ranges = set.match_ranges("a bar, a beer").unwrap();
+    assert_eq!(ranges.len(), 2u);
+    assert_eq!((ranges[0].index, ranges[0].start, ranges[0].end), (1u, 2u, 5u));
+    assert_eq!((ranges[1].index, ranges[1].start, ranges[1].end), (3u, 10u, 12u));
+}
+
+#[test]
+fn test_pcre_set_with_options() {
+    let mut caseless: EnumSet<CompileOption> = EnumSet::empty();
+    caseless.add(pcre::Caseless);
+    let mut anchored: EnumSet<CompileOption> = EnumSet::empty();
+    anchored.add(pcre::Anchored);
+    let no_options: EnumSet<CompileOption> = EnumSet::empty();
+    let set = PcreSet::with_options([("abc", caseless), ("abc", no_options.clone()), ("b", anchored), ("b", no_options)]).unwrap();
+    assert_eq!(set.matches("ABC").unwrap(), ~[0u]);
+    assert_eq!(set.matches("abc").unwrap(), ~[0u, 1u, 3u]);
+    assert_eq!(set.matches("bc").unwrap(), ~[2u, 3u]);
+
+    let err = PcreSet::new(["a", "("]).unwrap_err();
+    assert_eq!(err.index(), 1u);
+}
+
+#[test]
+fn test_pcre_set_verbs() {
+    // Combined, (*COMMIT) would keep the second alternative from being tried.
+    let set = PcreSet::new(["a(*COMMIT)b", "ac", "x(*ACCEPT)y"]).unwrap();
+    assert_eq!(set.matches("ac").unwrap(), ~[1u]);
+    assert_eq!(set.matches("xz").unwrap(), ~[2u]);
+}
+
+#[test]
+fn test_pcre_set_inline_extended() {
+    // Combined, the comment would swallow the end of the alternative.
+    let set = PcreSet::new(["(?x)a#c", "b"]).unwrap();
+    assert_eq!(set.matches("a").unwrap(), ~[0u]);
+    assert_eq!(set.matches("b").unwrap(), ~[1u]);
+}
+
+#[test]
+fn test_pcre_set_backtracking() {
+    // Each pattern matches at once on its own. Once a match is recorded, libpcre must not
+    // backtrack into the many other ways that (?:a|aa)+ could match the same text.
+    let set = PcreSet::new(["(?:a|aa)+c", "x"]).unwrap();
+    let subject = "a".repeat(40) + "c";
+    assert_eq!(set.matches(subject).unwrap(), ~[0u]);
+}
+
+#[test]
+fn test_pattern_cache() {
+    let cache = PatternCache::new(2u);
+    let re = cache.compile("a+b").unwrap();
+    assert!(re.exec("xaab").unwrap().is_some());
+    cache.compile("a+b").unwrap();
+    let mut caseless: EnumSet<CompileOption> = EnumSet::empty();
+    caseless.add(pcre::Caseless);
+    let re = cache.compile_with_options("a+b", &caseless).unwrap();
+    assert!(re.exec("AAB").unwrap().is_some());
+    assert_eq!(cache.len(), 2u);
+
+    // "a+b" was used more recently than the caseless version, which is evicted.
+    cache.compile("a+b").unwrap();
+    let mut jit: EnumSet<StudyOption> = EnumSet::empty();
+    jit.add(pcre::StudyJitCompile);
+    cache.compile_and_study("c", &EnumSet::empty(), &jit).unwrap();
+    cache.compile("a+b").unwrap();
+    assert_eq!(cache.stats(), pcre::CacheStats { hits: 3u, misses: 3u, evictions: 1u });
+
+    assert!(cache.compile("(").is_err());
+    assert_eq!(cache.len(), 2u);
+    cache.set_capacity(1u);
+    assert_eq!(cache.len(), 1u);
+    assert_eq!(cache.stats().evictions, 2u);
+}
+
+#[test]
+fn test_pattern_cache_across_tasks() {
+    let cache = PatternCache::new(10u);
+    let futures = vec::from_fn(8u, |i| {
+        let cache = cache.clone();
+        Future::spawn(proc() {
+            let re = cache.compile(format!("x{:u}|shared", i % 2)).unwrap();
+            re.exec("shared").unwrap().is_some()
+        })
+    });
+    for mut future in futures.move_iter() {
+        assert!(future.get());
+    }
+    assert_eq!(cache.len(), 2u);
+    let stats = cache.stats();
+    assert_eq!(stats.hits + stats.misses, 8u);
+}
+
+#[test]
+fn test_pcre_builder() {
+    let re = PcreBuilder::new("^b.r$").flags("ms").caseless().jit().match_limit(10000u).build().unwrap();
+    assert!(re.exec("foo\nBAR").unwrap().is_some());
+    assert_eq!(re.match_limit(), Some(10000u));
+    assert!(re.info().unwrap().study_size > 0u || re.is_jit_compiled());
+
+    let re = PcreBuilder::new("a.b").newline(pcre::NewlineCR).newline(pcre::NewlineCRLF).build().unwrap();
+    assert!(re.info().unwrap().options.contains_elem(pcre::NewlineCRLF));
+    assert!(!re.info().unwrap().options.contains_elem(pcre::NewlineCR));
+    assert!(re.exec("a\rb").unwrap().is_some());
+
+    // Extra options do not require the regular expression to be studied.
+    let mut mark: EnumSet<ExtraOption> = EnumSet::empty();
+    mark.add(pcre::ExtraMark);
+    let re = PcreBuilder::new("(*MARK:m)x").extra_options(&mark).build().unwrap();
+    assert_eq!(re.exec("x").unwrap().unwrap().mark(), Some("m"));
+
+    // Options which need a value are ignored, rather than applying a match limit of 0.
+    let mut match_limit: EnumSet<ExtraOption> = EnumSet::empty();
+    match_limit.add(pcre::ExtraMatchLimit);
+    match_limit.add(pcre::ExtraTables);
+    let re = PcreBuilder::new("a+b").extra_options(&match_limit).build().unwrap();
+    assert!(re.exec("aab").unwrap().is_some());
+}
+
+#[test]
+fn test_pcre_builder_errors() {
+    match PcreBuilder::new("a").flags("iq").build() {
+        Err(pcre::UnknownFlagError('q')) => (),
+        _ => fail!("expected UnknownFlagError")
+    }
+    match PcreBuilder::new("(").caseless().build() {
+        Err(pcre::CompileError(err)) => assert_eq!(err.offset(), 1u),
+        _ => fail!("expected CompileError")
+    }
+}
+
+#[test]
+fn test_matches_empty() {
+    // a* matches the empty string between the runs of a's, as in Perl.
+    let re = Pcre::compile("a*").unwrap();
+    let ranges: ~[(uint, uint)] = re.matches("baaac").map(|m| (m.group_start(0u), m.group_end(0u))).collect();
+    assert_eq!(ranges, ~[(0u, 0u), (1u, 4u), (4u, 4u), (5u, 5u)]);
+    assert_eq!(re.matches("").count(), 1u);
+
+    // Zero-width assertions match once at each position.
+    let re = Pcre::compile("\\b").unwrap();
+    let starts: ~[uint] = re.matches("ab cd").map(|m| m.group_start(0u)).collect();
+    assert_eq!(starts, ~[0u, 2u, 3u, 5u]);
+
+    // Empty matches advance by whole characters.
+    let re = Pcre::compile("x*").unwrap();
+    let starts: ~[uint] = re.matches("éé").map(|m| m.group_start(0u)).collect();
+    assert_eq!(starts, ~[0u, 2u, 4u]);
+
+    assert_eq!(re.replace_all("abc", "-").unwrap().as_slice(), "-a-b-c-");
+}
+
+#[test]
+fn test_matches_empty_crlf() {
+    let mut crlf: EnumSet<CompileOption> = EnumSet::empty();
+    crlf.add(pcre::NewlineCRLF);
+    crlf.add(pcre::Multiline);
+    let re = Pcre::compile_with_options("$", &crlf).unwrap();
+    let starts: ~[uint] = re.matches("a\r\nb").map(|m| m.group_start(0u)).collect();
+    // No match is found between the CR and the LF.
+    assert_eq!(starts, ~[1u, 4u]);
+
+    // With (*ANY), both ^ and $ would match between the CR and the LF.
+    let re = Pcre::compile("(*ANY)(?m)(?:$|^)").unwrap();
+    let starts: ~[uint] = re.matches("a\r\nb").map(|m| m.group_start(0u)).collect();
+    assert_eq!(starts, ~[0u, 1u, 3u, 4u]);
+}
+
+#[test]
+fn test_mark_without_study() {
+    // Marks are reported without studying or setting ExtraMark.
+    let re = Pcre::compile("X(*MARK:A)Y|X(*MARK:B)Z").unwrap();
+    let m = re.exec("XZ").unwrap().unwrap();
+    assert_eq!(m.mark(), Some("B"));
+
+    // After a failed match, the last mark passed is reported.
+    let no_options: EnumSet<ExecOption> = EnumSet::empty();
+    let mut data = re.match_data();
+    match re.exec_with_match_data("XW", 0u, &no_options, &mut data).unwrap() {
+        pcre::NoMatch => (),
+        _ => fail!("expected no match")
+    }
+    assert_eq!(data.mark(), Some("B"));
+    match re.exec_with_match_data("XY", 0u, &no_options, &mut data).unwrap() {
+        pcre::Full(m) => assert_eq!(m.mark(), Some("A")),
+        _ => fail!("expected a match")
+    }
+    assert_eq!(data.mark(), Some("A"));
+    re.exec_with_match_data("W", 0u, &no_options, &mut data).unwrap();
+    assert_eq!(data.mark(), None);
+
+    let re = Pcre::compile("(*MARK:a)a|(*MARK:b)b|c").unwrap();
+    let marks: ~[Option<~str>] = re.matches("abc").map(|m| m.mark().map(|mark| mark.to_owned())).collect();
+    assert_eq!(marks, ~[Some(~"a"), Some(~"b"), None]);
+}
+
+#[test]
+fn test_mark_partial() {
+    let re = Pcre::compile("(*MARK:start)abc(*MARK:end)d").unwrap();
+    let mut partial: EnumSet<ExecOption> = EnumSet::empty();
+    partial.add(pcre::ExecPartialSoft);
+    let mut data = re.match_data();
+    match re.exec_with_match_data("xab", 0u, &partial, &mut data).unwrap() {
+        pcre::Partial { start, end } => assert_eq!((start, end), (1u, 3u)),
+        _ => fail!("expected a partial match")
+    }
+    assert_eq!(data.mark(), Some("start"));
+}
+
+#[test]
+fn test_exec_shared() {
+    // Matching only borrows the regular expression, so it can be shared.
+    let re = Arc::new(Pcre::compile("(\\d+)").unwrap());
+    let shared: &Pcre = &*re;
+    assert_eq!(shared.exec("ab12").unwrap().unwrap().group(1u), "12");
+    assert!(shared.exec("ab").unwrap().is_none());
+}
+
+#[test]
+fn test_exec_into() {
+    let re = Pcre::compile("(\\w+)=(\\d+)?").unwrap();
+    let mut data = re.match_data();
+    let no_options: EnumSet<ExecOption> = EnumSet::empty();
+
+    assert!(re.exec_into("key=42", 0u, &no_options, &mut data).unwrap());
+    assert_eq!(data.string_count(), 3u);
+    assert_eq!(data.range(0u), Some((0u, 6u)));
+    assert_eq!(data.get("key=42", 1u), Some("key"));
+    assert_eq!(data.get("key=42", 2u), Some("42"));
+
+    // The same MatchData is reused for the next subject.
+    assert!(re.exec_into("answer=", 0u, &no_options, &mut data).unwrap());
+    assert_eq!(data.get("answer=", 1u), Some("answer"));
+    assert_eq!(data.get("answer=", 2u), None);
+
+    assert!(!re.exec_into("nothing", 0u, &no_options, &mut data).unwrap());
+    assert_eq!(data.string_count(), 0u);
+    assert_eq!(data.range(0u), None);
+}
+
+#[test]
+fn test_is_match_count_matches() {
+    let re = Pcre::compile("(a)(b)?").unwrap();
+    assert!(re.is_match("xxab").unwrap());
+    assert!(!re.is_match("xxb").unwrap());
+
+    assert_eq!(re.count_matches("abaxab").unwrap(), 3u);
+    assert_eq!(re.count_matches("xyz").unwrap(), 0u);
+
+    // Empty matches are counted like those of a MatchIterator.
+    let re = Pcre::compile("x*").unwrap();
+    assert_eq!(re.count_matches("axxb").unwrap(), re.matches("axxb").count());
+}
+
+#[test]
+fn test_exec_subslice() {
+    // The subject is passed to libpcre without being copied, so only its length keeps
+    // libpcre from looking at the rest of the string.
+    let s = "abcd\x00abcd";
+    let re = Pcre::compile("c(?!d)").unwrap();
+    assert!(re.exec(s).unwrap().is_none());
+    assert_eq!(re.exec(s.slice_to(3u)).unwrap().unwrap().group_start(0u), 2u);
+    assert_eq!(re.matches(s.slice(2u, 8u)).count(), 1u);
+    assert!(re.is_match(s.slice_from(5u).slice_to(3u)).unwrap());
+    assert_eq!(re.exec_bytes(s.as_bytes().slice_to(3u)).unwrap().unwrap().group_start(0u), 2u);
+}
+
+#[test]
+fn test_compilation_error_kind() {
+    let err = Pcre::compile("(abc").unwrap_err();
+    assert_eq!(err.kind(), pcre::MissingParenthesisError);
+    assert_eq!(err.code(), 14);
+    assert_eq!(err.offset(), 4u);
+
+    assert_eq!(Pcre::compile("[abc").unwrap_err().kind(), pcre::MissingBracketError);
+    assert_eq!(Pcre::compile("a{2,1}").unwrap_err().kind(), pcre::QuantifierOutOfOrderError);
+    assert_eq!(Pcre::compile("(?<=a+)b").unwrap_err().kind(), pcre::LookbehindNotFixedLengthError);
+    assert_eq!(Pcre::compile("abc\\").unwrap_err().kind(), pcre::BackslashAtEndError);
+    assert!(Pcre::compile("a{2,1}").unwrap_err().suggestion().unwrap().contains("\\{"));
+    assert!(Pcre::compile("(?<=a+)b").unwrap_err().suggestion().is_some());
+    assert_eq!(Pcre::compile("(?<n>a)(?<n>b)").unwrap_err().suggestion(), None);
+}
+
+#[test]
+fn test_compilation_error_render() {
+    let err = Pcre::compile("(abc").unwrap_err();
+    let rendered = err.render();
+    let lines: ~[&str] = rendered.lines().collect();
+    assert_eq!(lines[0], "compilation failed at offset 4: missing )");
+    assert_eq!(lines[1], "(abc");
+    assert_eq!(lines[2], "    ^");
+    assert!(lines[3].starts_with("help: "));
+
+    // Only the line of an extended pattern which contains the error is shown, and the
+    // caret is placed by characters rather than bytes.
+    let mut options: EnumSet<CompileOption> = EnumSet::empty();
+    options.add(pcre::Extended);
+    let err = Pcre::compile_with_options("café # ok\n(é a", &options).unwrap_err();
+    let rendered = err.render();
+    let lines: ~[&str] = rendered.lines().collect();
+    assert_eq!(lines[1], "(é a");
+    assert_eq!(lines[2], "    ^");
+}
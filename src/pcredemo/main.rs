@@ -13,10 +13,9 @@ extern crate collections;
 extern crate getopts;
 extern crate pcre;
 
-use collections::treemap::{TreeMap};
 use collections::enum_set::{EnumSet};
 use getopts::{OptGroup, getopts, optflag};
-use pcre::{CompileOption, Match, Pcre, pcre_version};
+use pcre::{CompileOption, ExecOption, Match, Pcre, build_config, pcre_version};
 use std::io::stdio::stderr;
 use std::os;
 
@@ -31,26 +30,42 @@ fn print_usage(program: &str, opts: &[OptGroup]) {
 
 fn print_version_info() {
     println!("rust-pcre 0.1 compiled against libpcre {}", pcre_version());
+
+    let config = build_config();
+    let yes_no = |supported: bool| if supported { "yes" } else { "no" };
+    println!("  UTF-8 support: {}", yes_no(config.utf8));
+    println!("  Unicode properties support: {}", yes_no(config.unicode_properties));
+    match config.jit_target {
+        None => println!("  JIT support: no"),
+        Some(ref jit_target) => println!("  JIT support: yes ({})", *jit_target)
+    }
+    println!("  Link size: {:u}", config.link_size);
+    println!("  Default match limit: {:u}", config.match_limit);
+    println!("  Default recursion limit: {:u}", config.match_limit_recursion);
+    println!("  Match recursion uses {}", if config.stack_recursion { "stack" } else { "heap" });
 }
 
-fn print_match(m: &Match, name_table: &TreeMap<~str, ~[uint]>) {
+fn print_match(m: &Match, name_count: uint) {
     println!("Match succeeded at offset {:u}", m.group_start(0u));
 
     // Show captured substrings by number.
     let mut i = 0u;
     while i < m.string_count() {
-        println!("{:2u}: {:s}", i, m.group(i));
+        match m.get(i) {
+            None => println!("{:2u}: <unset>", i),
+            Some(group) => println!("{:2u}: {:s}", i, group)
+        }
         i += 1;
     }
 
-    let name_count = name_table.len();
     if name_count <= 0 {
         println!("No named substrings");
     } else {
         println!("Named substrings:");
-        for (name, n_vec) in name_table.iter() {
-            for n in n_vec.iter() {
-                println!("({:u}) {:s}: {:s}", *n, *name, m.group(*n));
+        for (name, opt_group) in m.named_groups() {
+            match opt_group {
+                None => println!("{:s}: <unset>", name),
+                Some(group) => println!("{:s}: {:s}", name, group)
             }
         }
     }
@@ -107,40 +122,86 @@ fn main() {
     compile_options.add(pcre::DupNames);
     let re = match Pcre::compile_with_options(pattern, &compile_options) {
         Err(err) => {
-            stderr().write_line(format!("Error: The pattern could not be compiled: {:s}", err.to_str()));
+            stderr().write_line(format!("Error: The pattern could not be compiled: {:s}", err.render()));
             os::set_exit_status(1);
             return;
         },
         Ok(re) => re
     };
-    let name_table = re.name_table();
+    let name_count = re.name_count();
 
     let opt_m = re.exec(subject);
     let m = match opt_m {
-        None => {
+        Err(err) => {
+            stderr().write_line(format!("Error: Matching failed: {:s}", err.to_str()));
+            os::set_exit_status(1);
+            return;
+        },
+        Ok(None) => {
             println!("No match");
             os::set_exit_status(1);
             return;
         }
-        Some(m) => m
+        Ok(Some(m)) => m
     };
-    print_match(&m, &name_table);
+    print_match(&m, name_count);
 
     if find_all {
+        // After an empty match, the next match attempt must not match the empty string at
+        // the same offset. If it fails, move on by one character, or by two if CRLF is a
+        // newline and the offset is at a CRLF.
+        let info = re.info().unwrap();
+        let newlines = [pcre::NewlineCR, pcre::NewlineLF, pcre::NewlineCRLF, pcre::NewlineAny, pcre::NewlineAnyCRLF];
+        let newline = match newlines.iter().find(|&&newline| info.options.contains_elem(newline)) {
+            Some(&newline) => newline,
+            None => build_config().newline
+        };
+        let crlf_is_newline = match newline {
+            pcre::NewlineCRLF | pcre::NewlineAny | pcre::NewlineAnyCRLF => true,
+            _ => false
+        };
+
+        let mut last_start = m.group_start(0);
         let mut start_offset = m.group_end(0);
         loop {
-            let opt_m = re.exec_from(subject, start_offset);
-            let m = match opt_m {
-                None => {
+            let mut exec_options: EnumSet<ExecOption> = EnumSet::empty();
+            if last_start == start_offset {
+                // The previous match was empty.
+                if start_offset == subject.len() {
                     println!("\nNo more matches");
                     return;
                 }
-                Some(m) => m
+                exec_options.add(pcre::ExecNotEmptyAtStart);
+                exec_options.add(pcre::ExecAnchored);
+            }
+
+            let opt_m = re.exec_from_with_options(subject, start_offset, &exec_options);
+            let m = match opt_m {
+                Err(err) => {
+                    stderr().write_line(format!("Error: Matching failed: {:s}", err.to_str()));
+                    os::set_exit_status(1);
+                    return;
+                },
+                Ok(None) => {
+                    if exec_options.is_empty() {
+                        println!("\nNo more matches");
+                        return;
+                    }
+                    // There is no non-empty match at the offset of the empty match.
+                    start_offset = if crlf_is_newline && subject.slice_from(start_offset).starts_with("\r\n") {
+                        start_offset + 2
+                    } else {
+                        subject.char_range_at(start_offset).next
+                    };
+                    continue;
+                }
+                Ok(Some(m)) => m
             };
 
             println!("");
-            print_match(&m, &name_table);
+            print_match(&m, name_count);
 
+            last_start = m.group_start(0);
             start_offset = m.group_end(0);
         }
     }